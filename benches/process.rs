@@ -0,0 +1,83 @@
+// Throughput of KeyRemover::process with the vectored and drain-based writers and of the search of structural characters
+//  - Compare with the other revision by the saved baseline of criterion
+//    $ cargo bench --bench process -- --save-baseline before
+//    $ cargo bench --bench process -- --baseline before
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
+use json_key_remover::{KeyRemover, search};
+
+// Array of records of which some keys are removed (about 8MB)
+fn get_sample_json() -> Vec<u8> {
+    let mut json = String::from("[\n");
+    for idx in 0..40_000 {
+        if idx != 0 {
+            json.push_str(",\n");
+        }
+        json.push_str(&format!(
+            r#"  {{"id": {}, "name": "user_{}", "password": "{}", "meta": {{"debug": [1, 2, 3], "created": "2022-08-{:02}"}}, "tags": ["a", "b", "c"], "note": "{}"}}"#,
+            idx, idx, "x".repeat(16), idx % 28 + 1, "lorem ipsum ".repeat(8),
+        ));
+    }
+    json.push_str("\n]\n");
+    json.into_bytes()
+}
+
+fn process(c: &mut Criterion) {
+    let json = get_sample_json();
+    let keys_to_remove = vec!["password".to_string(), "debug".to_string()];
+
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    group.sample_size(20);
+    // Vectored write of the kept ranges and the old write path which transforms the buffers by drain
+    for (name, drain_writer) in [("vectored", false), ("drain", true)] {
+        for buffer_size in [4 * 1024, 64 * 1024, 1024 * 1024] {
+            group.bench_with_input(BenchmarkId::new(name, buffer_size), &buffer_size, |b, buffer_size| {
+                let mut output = Vec::with_capacity(json.len());
+                b.iter(|| {
+                    output.clear();
+                    let mut key_remover = KeyRemover::init(*buffer_size, keys_to_remove.clone()).drain_writer(drain_writer);
+                    key_remover.process(Cursor::new(&json), &mut output).unwrap();
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+// Pretty-printed arrays of numbers where the structural characters are searched over the indents and literals
+fn get_sample_numbers() -> Vec<u8> {
+    let mut json = String::from("[\n");
+    for idx in 0..100_000 {
+        json.push_str(&format!("    [\n        {}.{:06},\n        -{}e-10\n    ],\n", idx, idx * 7 % 1_000_000, idx * 31));
+    }
+    json.push_str("    []\n]\n");
+    json.into_bytes()
+}
+
+type FindStructural = fn(&[u8]) -> Option<usize>;
+
+fn structural_search(c: &mut Criterion) {
+    let json = get_sample_numbers();
+
+    let mut group = c.benchmark_group("structural_search");
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    for (name, find_structural) in [("table", search::scalar::find_structural as FindStructural), ("simd", search::find_structural)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut pos = 0;
+                let mut count = 0;
+                while let Some(found) = find_structural(&json[pos..]) {
+                    pos += found + 1;
+                    count += 1;
+                }
+                count
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process, structural_search);
+criterion_main!(benches);
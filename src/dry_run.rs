@@ -0,0 +1,154 @@
+// Removals listed instead of the output in dry-run mode or written to the side output
+
+use std::collections::VecDeque;
+
+use crate::scanner::ChrIndex;
+use crate::selector::{PathSegment, quote_json_string};
+use crate::formatter::{Formatter, OutputFormat};
+
+const PREVIEW_LENGTH: usize = 40; // Bytes of value in the preview
+
+// Member or object in array to remove
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovalMatch {
+    pub key: Option<String>, // None for the object in array
+    pub path: String,
+    pub line: Option<usize>, // Line of the record in NDJSON mode
+    pub start: usize, // Byte offset of the key or the object
+    pub end: usize, // Byte offset after the value
+    pub value: Vec<u8>, // Value in JSON (Truncated after the preview length in dry-run mode)
+}
+
+impl RemovalMatch {
+    // Value truncated with "..."
+    pub fn preview(&self) -> String {
+        let mut preview = String::from_utf8_lossy(&self.value[..self.value.len().min(PREVIEW_LENGTH)]).into_owned();
+        if self.value.len() > PREVIEW_LENGTH {
+            preview.push_str("...");
+        }
+        preview
+    }
+    // JSON object in a line for dry-run mode
+    pub fn to_json_line(&self) -> String {
+        format!(
+            "{{\"key\":{},\"path\":{},\"start\":{},\"end\":{},\"preview\":{}}}\n",
+            self.key_json(), quote_json_string(&self.path), self.start, self.end, quote_json_string(&self.preview()),
+        )
+    }
+    // JSON object in a line with the minified value
+    pub fn to_removed_line(&self) -> Vec<u8> {
+        let mut removed_line = format!("{{\"key\":{},\"path\":{},", self.key_json(), quote_json_string(&self.path)).into_bytes();
+        if let Some(line) = self.line {
+            removed_line.extend_from_slice(format!("\"line\":{},", line).as_bytes());
+        }
+        removed_line.extend_from_slice(b"\"value\":");
+        Formatter::new(OutputFormat::Minify).format(&self.value, &mut removed_line);
+        removed_line.extend_from_slice(b"}\n");
+        removed_line
+    }
+    fn key_json(&self) -> String {
+        match &self.key {
+            Some(key) => quote_json_string(key),
+            None => "null".to_string(),
+        }
+    }
+}
+
+// Removal found but the end is not read yet
+#[derive(Debug)]
+pub struct MatchCandidate {
+    key: Option<String>,
+    path: String,
+    start: usize, // Offset in the stream
+    value_start: usize,
+    value: Vec<u8>, // Bytes from the value start
+    value_limit: usize,
+}
+
+impl MatchCandidate {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // One more byte to know that it is truncated
+        let length = bytes.len().min(self.value_limit.saturating_add(1).saturating_sub(self.value.len()));
+        self.value.extend_from_slice(&bytes[..length]);
+    }
+}
+
+#[derive(Debug)]
+pub struct MatchRecorder {
+    value_limit: usize, // Bytes of value to record
+    base_offset: usize, // Offset of the document in the stream
+    line: Option<usize>, // Line of the record in NDJSON mode
+    buffer_offsets: VecDeque<usize>, // Offset of each buffer in the document from the first buffer
+    first_buffer_index: usize,
+    pub member: Option<MatchCandidate>, // Member of which value is being read
+    matches: Vec<RemovalMatch>,
+}
+
+impl Default for MatchRecorder {
+    fn default() -> Self {
+        Self {
+            value_limit: PREVIEW_LENGTH,
+            base_offset: 0,
+            line: None,
+            buffer_offsets: VecDeque::new(),
+            first_buffer_index: 0,
+            member: None,
+            matches: Vec::new(),
+        }
+    }
+}
+
+impl MatchRecorder {
+    // Record the whole value to write it to the side output
+    pub fn with_full_values() -> Self {
+        Self { value_limit: usize::MAX, ..Self::default() }
+    }
+    // New recorder with the same configuration
+    pub fn fresh(&self) -> Self {
+        Self { value_limit: self.value_limit, ..Self::default() }
+    }
+    pub fn new_candidate(&self, key: Option<String>, path: &[PathSegment], start: ChrIndex, value_start: ChrIndex) -> MatchCandidate {
+        MatchCandidate {
+            key,
+            path: path.iter().fold("$".to_string(), |path, segment| path + &segment.to_string()),
+            start: self.byte_offset(start),
+            value_start: self.byte_offset(value_start),
+            value: Vec::new(),
+            value_limit: self.value_limit,
+        }
+    }
+    pub fn set_record(&mut self, base_offset: usize, line: usize) {
+        self.base_offset = base_offset;
+        self.line = Some(line);
+    }
+    pub fn start_buffer(&mut self, offset: usize) {
+        self.buffer_offsets.push_back(offset);
+    }
+    // Positions in the buffers before the index are not recorded anymore
+    pub fn release_buffers(&mut self, until: usize) {
+        let count = until.saturating_sub(self.first_buffer_index).min(self.buffer_offsets.len());
+        self.buffer_offsets.drain(..count);
+        self.first_buffer_index += count;
+    }
+    pub fn record(&mut self, candidate: MatchCandidate, end: ChrIndex) {
+        let end = self.byte_offset(end);
+        // Bytes after the value can be fed
+        let mut value = candidate.value;
+        value.truncate(end.saturating_sub(candidate.value_start));
+        self.matches.push(RemovalMatch {
+            key: candidate.key,
+            path: candidate.path,
+            line: self.line,
+            start: candidate.start,
+            end,
+            value,
+        });
+    }
+    pub fn take_matches(&mut self) -> Vec<RemovalMatch> {
+        std::mem::take(&mut self.matches)
+    }
+    fn byte_offset(&self, chr_index: ChrIndex) -> usize {
+        let buffer_offset = chr_index.0.checked_sub(self.first_buffer_index).and_then(|queue_index| self.buffer_offsets.get(queue_index));
+        self.base_offset + buffer_offset.copied().unwrap_or_default() + chr_index.1
+    }
+}
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum KeyRemoverError {
+    // Failed to read input or to write output
+    Io(io::Error),
+    // Input is not JSON (line and column are 1-based, column is counted in bytes)
+    MalformedJson {
+        message: String,
+        offset: usize,
+        line: usize,
+        column: usize,
+    },
+    // Broken invariant of KeyRemover (bug)
+    Internal(String),
+}
+
+impl fmt::Display for KeyRemoverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::MalformedJson { message, offset, line, column } => {
+                write!(f, "Malformed JSON at line {} column {} (byte offset {}): {}", line, column, offset, message)
+            },
+            Self::Internal(message) => write!(f, "Internal error: {}", message),
+        }
+    }
+}
+
+impl Error for KeyRemoverError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KeyRemoverError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,146 @@
+// Reformat of the output while it is written
+//  - Output is expected to be valid JSON (Invalid input is formatted as far as possible)
+
+use std::num::NonZeroUsize;
+
+const OCB_CHR: u8 = b'{'; // Opening Curly Bracket
+const CCB_CHR: u8 = b'}'; // Closing Curly Bracket
+const OSB_CHR: u8 = b'['; // Opening Square Bracket
+const CSB_CHR: u8 = b']'; // Closing Square Bracket
+const DQ_CHR: u8 = b'"'; // Double Quotes
+const COMMA_CHR: u8 = b','; // Comma
+const COLON_CHR: u8 = b':'; // Colon
+const ESCAPE_CHR: u8 = b'\\'; // Escape
+const NEWLINE_CHR: u8 = b'\n';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Preserve, // Whitespace of the input is remained
+    Minify, // Whitespace outside of strings is removed
+    Indent(NonZeroUsize), // Pretty-printed with the spaces per depth
+}
+
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    output_format: OutputFormat,
+    depth: usize,
+    in_string: bool,
+    escape_next: bool,
+    container_opened: bool, // Newline after '{' or '[' is deferred for the empty container
+    value_written: bool, // Value is written at the root
+    values_separated: bool, // Whitespace after the root value separates the next document
+    line_ended: bool, // Newline is in the whitespace after the root value
+}
+
+impl Formatter {
+    pub fn new(output_format: OutputFormat) -> Self {
+        Self {
+            output_format,
+            depth: 0,
+            in_string: false,
+            escape_next: false,
+            container_opened: false,
+            value_written: false,
+            values_separated: false,
+            line_ended: false,
+        }
+    }
+    // Start the new document
+    pub fn reset(&mut self) {
+        *self = Self::new(self.output_format);
+    }
+    pub fn format(&mut self, bytes: &[u8], output: &mut Vec<u8>) {
+        for chr in bytes {
+            self.format_chr(*chr, output);
+        }
+    }
+    // Last document is ended with the newline if the input has the newline after it
+    pub fn finish(&mut self, output: &mut Vec<u8>) {
+        if self.line_ended {
+            output.push(NEWLINE_CHR);
+        }
+        self.values_separated = false;
+        self.line_ended = false;
+    }
+    fn format_chr(&mut self, chr: u8, output: &mut Vec<u8>) {
+        // (1) String is written as it is
+        if self.in_string {
+            if self.escape_next {
+                self.escape_next = false;
+            } else if chr == ESCAPE_CHR {
+                self.escape_next = true;
+            } else if chr == DQ_CHR {
+                self.in_string = false;
+            }
+            output.push(chr);
+            return
+        }
+        // (2) Whitespace is dropped
+        if chr.is_ascii_whitespace() {
+            if self.depth == 0 && self.value_written {
+                self.values_separated = true;
+                self.line_ended |= chr == NEWLINE_CHR;
+            }
+            return
+        }
+        // (3) Next document at the root
+        if self.depth == 0 {
+            if self.values_separated {
+                output.push(NEWLINE_CHR);
+                self.values_separated = false;
+                self.line_ended = false;
+            }
+            self.value_written = true;
+        }
+        let indent = match self.output_format {
+            OutputFormat::Indent(indent) => indent.get(),
+            _ => {
+                if chr == DQ_CHR {
+                    self.in_string = true;
+                }
+                match chr {
+                    OCB_CHR | OSB_CHR => self.depth += 1,
+                    CCB_CHR | CSB_CHR => self.depth = self.depth.saturating_sub(1),
+                    _ => {},
+                }
+                output.push(chr);
+                return
+            },
+        };
+        // (4) Pretty-print
+        match chr {
+            CCB_CHR | CSB_CHR => {
+                self.depth = self.depth.saturating_sub(1);
+                if !self.container_opened {
+                    push_newline(output, self.depth * indent);
+                }
+                self.container_opened = false;
+                output.push(chr);
+            },
+            COMMA_CHR => {
+                output.push(chr);
+                push_newline(output, self.depth * indent);
+            },
+            COLON_CHR => {
+                output.extend_from_slice(b": ");
+            },
+            _ => {
+                if self.container_opened {
+                    push_newline(output, self.depth * indent);
+                }
+                self.container_opened = matches!(chr, OCB_CHR | OSB_CHR);
+                match chr {
+                    OCB_CHR | OSB_CHR => self.depth += 1,
+                    DQ_CHR => self.in_string = true,
+                    _ => {},
+                }
+                output.push(chr);
+            },
+        }
+    }
+}
+
+fn push_newline(output: &mut Vec<u8>, spaces: usize) {
+    output.push(NEWLINE_CHR);
+    output.resize(output.len() + spaces, b' ');
+}
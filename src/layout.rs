@@ -0,0 +1,226 @@
+// Removal of the lines left blank by the removed members
+//  - Indentation before the removed member and the newline after it are also removed
+//    when nothing else is on its lines
+//  - Whitespace in the container is removed when all of its members are removed
+//    (Container is held from its opener, but the buffers skipped by the removals in it are released)
+
+use crate::scanner::{ChrIndex, Message};
+
+const NEWLINE_CHR: u8 = b'\n';
+
+#[derive(Debug)]
+pub struct LayoutCleaner {
+    blank_line_start: Option<ChrIndex>, // Start of the current line if it has only whitespace so far
+    first_newline: Option<ChrIndex>, // First newline after the last character except whitespace
+    pending_line: Option<PendingLine>,
+    containers: Vec<ContainerLayout>, // Open containers from the outermost
+    removing: bool, // Between the skip start and the skip end
+    removed_until: ChrIndex, // Boundary of the last skip end
+}
+
+// Removal waiting for the end of its line
+#[derive(Debug)]
+struct PendingLine {
+    line_start: ChrIndex,
+    skip_start_index: usize, // Index of the message in the queue
+    skip_end: Option<(usize, ChrIndex)>, // Index of the message and its boundary
+}
+
+// Container which can be left empty by the removals
+#[derive(Debug)]
+struct ContainerLayout {
+    content_start: ChrIndex, // Next to the opener
+    message_index: usize, // Index of the first message in the container
+    first_content: Option<ChrIndex>, // First character except whitespace not removed
+}
+
+impl ContainerLayout {
+    // Kept content is before the data resolved by the scanner
+    fn has_kept_content(&self, resolved_until: Option<ChrIndex>) -> bool {
+        self.first_content.is_some_and(|first_content| resolved_until.is_none_or(|resolved_until| first_content < resolved_until))
+    }
+}
+
+impl Default for LayoutCleaner {
+    fn default() -> Self {
+        Self {
+            blank_line_start: Some((0, 0)),
+            first_newline: None,
+            pending_line: None,
+            containers: Vec::new(),
+            removing: false,
+            removed_until: (0, 0),
+        }
+    }
+}
+
+impl LayoutCleaner {
+    pub fn blank_line_start(&self) -> Option<ChrIndex> {
+        self.blank_line_start
+    }
+    // Data from this index can be removed with the next removal
+    //  - Resolved until is the end of the data resolved by the scanner
+    pub fn held_from(&self, resolved_until: Option<ChrIndex>) -> Option<ChrIndex> {
+        let held_by_line = match &self.pending_line {
+            Some(pending_line) => Some(pending_line.line_start),
+            None => self.blank_line_start,
+        };
+        let held_by_container = self.first_empty_container(resolved_until).map(|container| container.content_start);
+        held_by_line.into_iter().chain(held_by_container).min()
+    }
+    // Messages from this index can be changed
+    pub fn held_message_index(&self, resolved_until: Option<ChrIndex>) -> Option<usize> {
+        let held_by_line = self.pending_line.as_ref().map(|pending_line| pending_line.skip_start_index);
+        let held_by_container = self.first_empty_container(resolved_until).map(|container| container.message_index);
+        held_by_line.into_iter().chain(held_by_container).min()
+    }
+    pub fn drain_messages(&mut self, count: usize) {
+        if let Some(pending_line) = self.pending_line.as_mut() {
+            pending_line.skip_start_index -= count;
+            if let Some((skip_end_index, _)) = pending_line.skip_end.as_mut() {
+                *skip_end_index -= count;
+            }
+        }
+        for container in &mut self.containers {
+            container.message_index = container.message_index.saturating_sub(count);
+        }
+    }
+    pub fn truncate_messages(&mut self, length: usize) {
+        if self.pending_line.as_ref().is_some_and(|pending_line| pending_line.skip_start_index >= length) {
+            self.pending_line = None;
+        }
+    }
+    // Opener of the container is at the position
+    pub fn open_container(&mut self, queue: &[Message], position: ChrIndex) {
+        self.containers.push(ContainerLayout {
+            content_start: (position.0, position.1 + 1),
+            message_index: queue.len(),
+            first_content: None,
+        });
+    }
+    // Closer of the container is at the position
+    pub fn close_container(&mut self, queue: &mut Vec<Message>, position: ChrIndex) {
+        let container = match self.containers.pop() {
+            Some(container) => container,
+            None => return,
+        };
+        let emptied = container.first_content.is_none() && queue.len() > container.message_index;
+        if emptied && !self.in_removal(position) {
+            // Whitespace and removals between the opener and the closer
+            queue.truncate(container.message_index);
+            self.truncate_messages(container.message_index);
+            queue.push(Message::SkipStartFrom(container.content_start));
+            queue.push(Message::SkipEndPreviousTo(position));
+        }
+    }
+    // Skip start is the last message
+    //  - Line start is None if the line has other characters before the removed member
+    pub fn start_removal(&mut self, queue: &[Message], line_start: Option<ChrIndex>) {
+        // Characters already read from the skip start are removed
+        let skip_start = queue[queue.len() - 1].boundary();
+        for container in &mut self.containers {
+            if container.first_content.is_some_and(|first_content| first_content >= skip_start) {
+                container.first_content = None;
+            }
+        }
+        self.removing = true;
+        self.pending_line = line_start.map(|line_start| PendingLine {
+            line_start,
+            skip_start_index: queue.len() - 1,
+            skip_end: None,
+        });
+    }
+    // Skip end is the last message
+    pub fn end_removal(&mut self, queue: &mut [Message], position: ChrIndex) {
+        let skip_end_index = queue.len() - 1;
+        let boundary = queue[skip_end_index].boundary();
+        self.removing = false;
+        self.removed_until = boundary;
+        if let Some(pending_line) = self.pending_line.as_mut() {
+            pending_line.skip_end = Some((skip_end_index, boundary));
+            if boundary < position {
+                // Whitespace after the deferred end is already read
+                match self.first_newline {
+                    Some(newline_position) => self.remove_line(queue, newline_position),
+                    None => self.pending_line = None,
+                }
+            }
+        }
+    }
+    // Track the character of the position
+    pub fn track(&mut self, queue: &mut [Message], chr: u8, is_whitespace: bool, position: ChrIndex) {
+        if let Some(PendingLine { skip_end: Some((_, boundary)), .. }) = self.pending_line {
+            if position >= boundary {
+                if chr == NEWLINE_CHR {
+                    self.remove_line(queue, position);
+                } else if !is_whitespace {
+                    self.pending_line = None;
+                }
+            }
+        }
+        if !is_whitespace {
+            self.blank_line_start = None;
+            self.first_newline = None;
+            self.find_content(position);
+        } else if chr == NEWLINE_CHR {
+            self.blank_line_start = Some((position.0, position.1 + 1));
+            self.first_newline.get_or_insert(position);
+        }
+    }
+    // Track the characters skipped at once
+    pub fn track_all(&mut self, queue: &mut [Message], bytes: &[u8], in_string: bool, start: ChrIndex) {
+        let is_whitespace = |chr: u8| !in_string && matches!(chr, b' ' | b'\t' | b'\n' | b'\r');
+        // (1) Decide the pending removal with the leading whitespace
+        for (idx, chr) in bytes.iter().enumerate() {
+            if !matches!(self.pending_line, Some(PendingLine { skip_end: Some(_), .. })) {
+                break
+            }
+            self.track(queue, *chr, is_whitespace(*chr), (start.0, start.1 + idx));
+        }
+        // (2) Track the trailing whitespace only
+        if let Some(first) = bytes.iter().position(|chr| !is_whitespace(*chr)) {
+            self.find_content((start.0, start.1 + first));
+        }
+        let trailing_start = match bytes.iter().rposition(|chr| !is_whitespace(*chr)) {
+            Some(last) => {
+                self.blank_line_start = None;
+                self.first_newline = None;
+                last + 1
+            },
+            None => 0,
+        };
+        for (idx, chr) in bytes.iter().enumerate().skip(trailing_start) {
+            if *chr == NEWLINE_CHR {
+                self.blank_line_start = Some((start.0, start.1 + idx + 1));
+                self.first_newline.get_or_insert((start.0, start.1 + idx));
+            }
+        }
+    }
+    // End of input before the end of line
+    pub fn finish(&mut self) {
+        self.pending_line = None;
+        self.containers.clear();
+    }
+    // Outermost container not having the kept content yet
+    fn first_empty_container(&self, resolved_until: Option<ChrIndex>) -> Option<&ContainerLayout> {
+        self.containers.iter().find(|container| !container.has_kept_content(resolved_until))
+    }
+    fn in_removal(&self, position: ChrIndex) -> bool {
+        self.removing || position < self.removed_until
+    }
+    // Character except whitespace is at the position
+    fn find_content(&mut self, position: ChrIndex) {
+        if self.in_removal(position) {
+            return
+        }
+        if let Some(container) = self.containers.last_mut() {
+            container.first_content.get_or_insert(position);
+        }
+    }
+    fn remove_line(&mut self, queue: &mut [Message], newline_position: ChrIndex) {
+        if let Some(PendingLine { line_start, skip_start_index, skip_end: Some((skip_end_index, _)) }) = self.pending_line.take() {
+            queue[skip_start_index] = Message::SkipStartFrom(line_start);
+            queue[skip_end_index] = Message::SkipEndTo(newline_position);
+        }
+    }
+}
@@ -1,20 +1,105 @@
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write, ErrorKind, IoSlice};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 mod scanner;
 use scanner::{
-    Scanner,
-    ScannerState,
+    Scanner, ScanStats,
     ChrIndex, Message,
 };
+pub use scanner::KeyComparison;
+mod selector;
+pub use selector::{Selector, PathSegment, FilterMode, KeyNormalization, replacement_to_json, quote_json_string, split_outside_brackets, split_once_outside_brackets};
+use selector::{Filter, ValueCondition, ConditionTarget};
+mod error;
+pub use error::KeyRemoverError;
+mod validator;
+mod layout;
+use layout::LayoutCleaner;
+mod formatter;
+mod dry_run;
+pub use dry_run::RemovalMatch;
+use dry_run::MatchRecorder;
+pub use formatter::OutputFormat;
+use formatter::Formatter;
+use validator::Validator;
+mod parallel;
+#[doc(hidden)]
+pub mod search; // Public to benchmark the SIMD search against the scalar search
+mod trie;
+
+// Buffers held to reuse are limited after the long skip is resolved
+const MAX_FREE_BUFFERS: usize = 4;
 
 #[derive(Debug)]
 pub struct KeyRemover {
     scanner: Scanner,
     buffer_size: usize,
     first_buffer_index: usize,
-    buffer_queue: Vec<Vec<u8>>,
-    buffer_length_queue: Vec<usize>,
-    message_queue: Vec<Message>,
+    buffer_queue: VecDeque<Vec<u8>>,
+    buffer_length_queue: VecDeque<usize>,
+    free_buffers: Vec<Vec<u8>>, // Released buffers to reuse
+    message_queue: VecDeque<Message>,
+    mode: Mode,
+    written_chr_index: ChrIndex, // Data before this index is written or skipped
+    bad_record_policy: Option<BadRecordPolicy>, // Process each line as a document in NDJSON mode
+    threads: usize, // Worker threads in NDJSON mode
+    formatter: Option<Formatter>, // Reformat the output
+    dry_run: bool, // Write the removals instead of the output
+    drain_writer: bool, // Old write path to compare in the benchmark
+    removed_out: Option<RemovedOut>,
+    removed_lines: Option<Vec<u8>>, // Removals waiting to be written to the side output
+    stats: ProcessStats,
+    peak_held_bytes: usize, // Maximum bytes of the buffers held in the queue at once
+}
+
+// Side output of the removed members
+struct RemovedOut(Box<dyn Write + Send>);
+
+impl fmt::Debug for RemovedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RemovedOut")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    pub removed_keys: BTreeMap<String, usize>, // Count of removed members by key
+    pub removed_elements: usize, // Count of objects removed from arrays by their members
+    pub max_depth: usize, // Maximum nesting depth of containers
+    pub elapsed: Duration,
+    // NDJSON mode
+    pub records: usize,
+    pub bad_records: Vec<BadRecord>,
+}
+
+impl ProcessStats {
+    // Removals are summed up and the depth is the maximum
+    fn add_scan_stats(&mut self, scan_stats: ScanStats) {
+        for (key, count) in scan_stats.removed_keys {
+            *self.removed_keys.entry(key).or_default() += count;
+        }
+        self.removed_elements += scan_stats.removed_elements;
+        self.max_depth = self.max_depth.max(scan_stats.max_depth);
+    }
+}
+
+// Way to deal with the malformed record in NDJSON mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadRecordPolicy {
+    Fail, // Stop with the error
+    Skip, // Drop the record
+    PassThrough, // Write the record as it is
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadRecord {
+    pub line: usize, // 1-based line of the record
+    pub message: String,
 }
 
 impl KeyRemover {
@@ -22,214 +107,632 @@ impl KeyRemover {
         buffer_size: usize,
         keys_to_remove: Vec<String>,
     ) -> Self {
-        let scanner = Scanner::new(keys_to_remove);
-
+        let selectors = keys_to_remove.iter().map(|key| Selector::key(key)).collect();
+        Self::init_with_selectors(buffer_size, selectors)
+    }
+    pub fn init_with_selectors(
+        buffer_size: usize,
+        selectors: Vec<Selector>,
+    ) -> Self {
+        let filter = Filter::new(selectors, FilterMode::Remove);
+        let scanner = Scanner::new(filter);
+        Self::init_with_scanner(buffer_size, scanner)
+    }
+    fn init_with_scanner(buffer_size: usize, scanner: Scanner) -> Self {
         Self {
             scanner,
-            buffer_size,
+            buffer_size: buffer_size.max(1),
             first_buffer_index: 0,
-            buffer_queue: Vec::new(),
-            buffer_length_queue: Vec::new(),
-            message_queue: Vec::new(),
+            buffer_queue: VecDeque::new(),
+            buffer_length_queue: VecDeque::new(),
+            free_buffers: Vec::new(),
+            message_queue: VecDeque::new(),
+            mode: Mode::Remain,
+            written_chr_index: (0, 0),
+            bad_record_policy: None,
+            threads: 1,
+            formatter: None,
+            dry_run: false,
+            drain_writer: false,
+            removed_out: None,
+            removed_lines: None,
+            stats: ProcessStats::default(),
+            peak_held_bytes: 0,
+        }
+    }
+    // Remove the selected keys or keep only the selected keys
+    //  - Containers which can have the selected member are streamed and left empty without it in keep mode
+    pub fn mode(mut self, mode: FilterMode) -> Self {
+        self.scanner.filter.set_mode(mode);
+        self
+    }
+    // Also remove the containers left without the selected member in keep mode
+    //  - Container of which member can be selected is held until it is closed (Memory grows with the container)
+    pub fn prune_empty(mut self, prune_empty: bool) -> Self {
+        self.scanner.prune_empty = prune_empty;
+        self
+    }
+    // Replace the value of selected key with the replacement (e.g. b"null", b"\"***\"")
+    pub fn redact(mut self, selector: Selector, replacement: Vec<u8>) -> Self {
+        self.scanner.filter.add_redaction(selector, replacement);
+        self
+    }
+    // Rename the key at any depth
+    pub fn rename(mut self, key: String, new_key: &str) -> Self {
+        self.scanner.filter.add_rename(key, new_key);
+        self
+    }
+    // Remove the member of which value is null
+    pub fn drop_nulls(mut self) -> Self {
+        self.scanner.filter.add_value_condition(ConditionTarget::Member, ValueCondition::Null);
+        self
+    }
+    // Remove the member of which value is "", [] or {}
+    pub fn drop_empty(mut self) -> Self {
+        self.scanner.filter.add_value_condition(ConditionTarget::Member, ValueCondition::Empty);
+        self
+    }
+    // Remove the selected member when its value is the JSON text (e.g. b"\"deleted\"", b"0")
+    //  - Value is compared without decoding (e.g. 1.0 is not 1)
+    //  - Only scalars, {} and [] are compared (Object or array with members never matches)
+    pub fn remove_if(mut self, selector: Selector, value: Vec<u8>) -> Self {
+        self.scanner.filter.add_value_condition(ConditionTarget::Member, ValueCondition::Equals(selector, value));
+        self
+    }
+    // Remove the object in array when its member at the selector is the JSON text (e.g. b"true" for "deleted")
+    //  - Only scalars, {} and [] are compared like remove_if
+    //  - Data of the object is held until the object is closed
+    pub fn remove_elements_if(mut self, selector: Selector, value: Vec<u8>) -> Self {
+        self.scanner.filter.add_value_condition(ConditionTarget::Element, ValueCondition::Equals(selector, value));
+        self
+    }
+    // Compare the decoded keys (default) or the raw bytes of keys
+    pub fn key_comparison(mut self, key_comparison: KeyComparison) -> Self {
+        self.scanner.key_comparison = key_comparison;
+        self
+    }
+    // Validate full JSON grammar while scanning and stop at the first error
+    //  - Data before the error can be already written
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.scanner.validator = if strict { Some(Validator::default()) } else { None };
+        self
+    }
+    // Remove the indentation and the newline of the removed member on its own lines
+    //  - Data at the start of line is held until the line is decided
+    pub fn layout_aware(mut self, layout_aware: bool) -> Self {
+        self.scanner.layout_cleaner = if layout_aware { Some(LayoutCleaner::default()) } else { None };
+        self
+    }
+    // Write the removals and redactions as JSON Lines (RemovalMatch::to_json_line) instead of the output
+    //  - Paths are listed with all keys at the cost of the early rejection of keys
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self.set_match_recorder();
+        self
+    }
+    // Write each removed member or object in array with its path to the second writer
+    //  - Redacted member is written with its original value
+    //  - JSON Lines of RemovalMatch::to_removed_line with the line of the record in NDJSON mode
+    //  - Paths are listed with all keys at the cost of the early rejection of keys
+    pub fn removed_out<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.removed_out = Some(RemovedOut(Box::new(writer)));
+        self.removed_lines = Some(Vec::new());
+        self.set_match_recorder();
+        self
+    }
+    fn set_match_recorder(&mut self) {
+        self.scanner.match_recorder = if self.removed_lines.is_some() {
+            Some(MatchRecorder::with_full_values())
+        } else if self.dry_run {
+            Some(MatchRecorder::default())
+        } else {
+            None
+        };
+    }
+    // Minify or pretty-print the output in the same pass
+    //  - Pretty-printed record is not a line in NDJSON mode
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.formatter = match output_format {
+            OutputFormat::Preserve => None,
+            _ => Some(Formatter::new(output_format)),
+        };
+        self
+    }
+    // Compare the keys after normalization (e.g. ignoring case)
+    //  - Regex is matched ignoring case and with "_" for its literal "-"
+    pub fn key_normalization(mut self, key_normalization: KeyNormalization) -> Self {
+        self.scanner.filter.set_key_normalization(key_normalization);
+        self
+    }
+    // Process each line as an independent JSON document (NDJSON, JSON Lines)
+    //  - Each record is validated in strict mode to find the bad records
+    pub fn ndjson(mut self, bad_record_policy: BadRecordPolicy) -> Self {
+        self.bad_record_policy = Some(bad_record_policy);
+        self
+    }
+    // Process the chunks of lines with worker threads in NDJSON mode
+    //  - Output is written in the order of input
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+    // Write the buffers transformed in place by drain and splice like the old write path
+    //  - Kept to compare with the vectored write in the benchmark
+    #[doc(hidden)]
+    pub fn drain_writer(mut self, drain_writer: bool) -> Self {
+        self.drain_writer = drain_writer;
+        self
+    }
+    // Validate the input in strict mode without writing output
+    //  - Strict mode is restored for the next process
+    pub fn validate<R: Read>(&mut self, reader: R) -> Result<ProcessStats, KeyRemoverError> {
+        let strict = self.scanner.validator.is_some();
+        if !strict {
+            self.scanner.validator = Some(Validator::default());
         }
+        let result = self.process(reader, std::io::sink());
+        if !strict {
+            self.scanner.validator = None;
+        }
+        result
     }
-    pub fn process<R, W>(&mut self, mut reader: R, mut writer: W) where
+    pub fn process<R, W>(&mut self, reader: R, writer: W) -> Result<ProcessStats, KeyRemoverError> where
         R: Read, W: Write,
     {
-        let mut mode = Mode::Remain;
-
-        // (1) Read first buffer
-        let mut first_buffer = vec![0; self.buffer_size];
-        let mut filled_byte_size = reader.read(&mut first_buffer).unwrap(); // TODO: Deal error
-        self.scanner.process_new_buffer(&first_buffer[..filled_byte_size]);
-        self.buffer_queue.push(first_buffer);
-        self.buffer_length_queue.push(filled_byte_size);
-
-        // (2) While file end
-        while filled_byte_size != 0 { // TODO: Handle with slow stream
-            // (1) Pull out messages
-            self.message_queue.append(&mut self.scanner.queue);
-
-            // (2) Get range of data to write
-            let optional_data_index_to_write = match self.scanner.state {
-                ScannerState::WaitingNextKey => {
-                    // Write all message and buffer
-                    let last_buffer_index_to_write = self.scanner.next_buffer_index - 1;
-                    let message_end_index = self.message_queue.len();
-
-                    Some((last_buffer_index_to_write, message_end_index))
-                },
-                ScannerState::CheckingValueRange => {
-                    if self.scanner.next_buffer_index == 1 {
-                        None
-                    } else {
-                        let last_buffer_index_to_write = self.scanner.next_buffer_index - 2;
-                        if last_buffer_index_to_write < self.first_buffer_index {
-                            None
-                        } else {
-                            let message_end_index = self.get_message_end_index(last_buffer_index_to_write);
-                            Some((last_buffer_index_to_write, message_end_index))
-                        }
-                    }
-                },
-                ScannerState::FindingNextComma => {
-                    let cached_skip_end_msg = &self.scanner.skip_end_msg_cache;
-                    let last_buffer_index_to_write = match self.get_optional_skip_end_index(cached_skip_end_msg) {
-                        Some(chr_index) => chr_index.0 - 1,
-                        None => panic!("Error 1"), // TODO: Unreachable error msg
-                    };
-                    if last_buffer_index_to_write < self.first_buffer_index {
-                        None
-                    } else {
-                        let message_end_index = self.get_message_end_index(last_buffer_index_to_write);
-                        Some((last_buffer_index_to_write, message_end_index))
-                    }
+        let started_at = Instant::now();
+        self.stats = ProcessStats::default();
+        self.peak_held_bytes = 0;
+        if let Some(formatter) = self.formatter.as_mut() {
+            formatter.reset();
+        }
+        if self.bad_record_policy.is_some() && self.scanner.validator.is_none() {
+            self.scanner.validator = Some(Validator::default());
+        }
+        let mut stats = match self.bad_record_policy {
+            Some(bad_record_policy) if self.threads > 1 => self.process_ndjson_in_parallel(reader, writer, bad_record_policy)?,
+            Some(bad_record_policy) => self.process_ndjson(reader, writer, bad_record_policy, RecordPosition::default())?,
+            None => self.process_document(reader, writer)?,
+        };
+        if let Some(removed_out) = self.removed_out.as_mut() {
+            removed_out.0.flush()?;
+        }
+        stats.elapsed = started_at.elapsed();
+        Ok(stats)
+    }
+    fn process_document<R, W>(&mut self, mut reader: R, mut writer: W) -> Result<ProcessStats, KeyRemoverError> where
+        R: Read, W: Write,
+    {
+        // State left by the previous (possibly failed) process is dropped
+        self.reset_state();
+        // (1) While file end
+        loop {
+            // (1) Load next buffer
+            //  - Partially filled buffer is scanned and queued with its filled length not to wait the slow stream
+            let mut next_buffer = self.free_buffers.pop().unwrap_or_else(|| vec![0; self.buffer_size]);
+            let filled_byte_size = match reader.read(&mut next_buffer) {
+                Ok(0) => {
+                    self.free_buffers.push(next_buffer);
+                    break
                 },
-                _ => {
-                    // ScannerState::MeetKeyCandOpener | ScannerState::ConfirmingKey | ScannerState::DefiningValueType
-                    // Defer
-                    let chr_index = self.scanner.key_cand_opener_index();
-                    if self.first_buffer_index < chr_index.0 {
-                        let last_buffer_index_to_write = chr_index.0 - 1;
-                        let message_end_index = self.get_message_end_index(last_buffer_index_to_write);
-                        Some((last_buffer_index_to_write, message_end_index))
-                    }else {
-                        None
-                    }
+                Ok(filled_byte_size) => filled_byte_size,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {
+                    self.free_buffers.push(next_buffer);
+                    continue
                 },
+                Err(err) => return Err(err.into()),
+            };
+            self.stats.bytes_read += filled_byte_size;
+            self.scanner.process_new_buffer(&next_buffer[..filled_byte_size])?;
+            //  - Short read is copied to be held with its filled length, not with the full buffer
+            let next_buffer = if filled_byte_size < self.buffer_size / 4 {
+                let compacted = next_buffer[..filled_byte_size].to_vec();
+                self.free_buffers.push(next_buffer);
+                compacted
+            } else {
+                next_buffer
             };
+            self.buffer_queue.push_back(next_buffer);
+            self.buffer_length_queue.push_back(filled_byte_size);
 
-            // (3) Write data
-            if let Some((last_buffer_index_to_write, message_end_index)) = optional_data_index_to_write {
-                // (1) Get messages
-                let mut messages_to_write: Vec<Message> = self.message_queue.drain(0..message_end_index).collect();
+            // (2) Write data of which skip range is resolved
+            self.write_resolved_data(&mut writer)?;
+            self.write_removed_lines()?;
+        }
 
-                // (2) Adjust messages by mode
-                if let Some(Message::SkipEndPreviousTo(chr_index)) = messages_to_write.first() {
-                    if chr_index.1 == 0 && chr_index.0 == self.first_buffer_index {
-                        messages_to_write.remove(0);
-                        mode = Mode::Remain;
-                    }
-                }
-                if let Mode::Skip = mode {
-                    messages_to_write.insert(0, Message::SkipStartFrom((self.first_buffer_index, 0)));
-                }
-                if let Some(Message::SkipStartFrom(_)) = messages_to_write.last() {
-                    messages_to_write.push(Message::SkipEndTo((
-                        last_buffer_index_to_write,
-                        self.buffer_length_queue[last_buffer_index_to_write-self.first_buffer_index]-1
-                    )));
-                    mode = Mode::Skip;
-                } else {
-                    mode = Mode::Remain;
-                }
+        // (2) Write remaining data
+        self.scanner.finish()?;
+        self.write_resolved_data(&mut writer)?;
+        self.write_removed_lines()?;
+        if let (Some(formatter), false) = (self.formatter.as_mut(), self.dry_run) {
+            let mut formatted = Vec::new();
+            formatter.finish(&mut formatted);
+            writer.write_all(&formatted)?;
+            self.stats.bytes_written += formatted.len();
+        }
+        self.stats.add_scan_stats(std::mem::take(&mut self.scanner.stats));
 
-                // (3) Transform buffers to write
-                messages_to_write.chunks(2).rev().for_each(|messages| {
-                    let skip_start_chr_index = match messages[0] {
-                        Message::SkipStartFrom(chr_index) => {
-                            (chr_index.0 - self.first_buffer_index, chr_index.1)
-                        },
-                        _ => panic!("Error 2"),
+        writer.flush()?;
+        Ok(self.stats.clone())
+    }
+    fn process_ndjson<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        bad_record_policy: BadRecordPolicy,
+        mut record_position: RecordPosition, // Position of the first record
+    ) -> Result<ProcessStats, KeyRemoverError> where
+        R: Read, W: Write,
+    {
+        let mut buffer = vec![0; self.buffer_size];
+        let mut record = Vec::new(); // Without newline
+
+        // (1) While file end
+        loop {
+            let filled_byte_size = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(filled_byte_size) => filled_byte_size,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            self.stats.bytes_read += filled_byte_size;
+
+            // Process each completed line
+            let mut line_start = 0;
+            while let Some(line_length) = buffer[line_start..filled_byte_size].iter().position(|chr| *chr == b'\n') {
+                record.extend_from_slice(&buffer[line_start..line_start+line_length]);
+                self.process_record(&record, true, &mut writer, bad_record_policy, &record_position)?;
+                record_position.line += 1;
+                record_position.offset += record.len() + 1;
+                record.clear();
+                line_start += line_length + 1;
+            }
+            record.extend_from_slice(&buffer[line_start..filled_byte_size]);
+        }
+
+        // (2) Last line without newline
+        if !record.is_empty() {
+            self.process_record(&record, false, &mut writer, bad_record_policy, &record_position)?;
+        }
+
+        writer.flush()?;
+        Ok(self.stats.clone())
+    }
+    fn process_record<W: Write>(
+        &mut self,
+        record: &[u8],
+        has_newline: bool,
+        writer: &mut W,
+        bad_record_policy: BadRecordPolicy,
+        record_position: &RecordPosition,
+    ) -> Result<(), KeyRemoverError> {
+        // (1) Blank line is not a record
+        //  - Only the removals are listed in dry-run mode
+        let dry_run = self.dry_run;
+        let output = if record.iter().all(|chr| chr.is_ascii_whitespace()) {
+            if dry_run {
+                return Ok(())
+            }
+            record.to_vec()
+        } else {
+            self.stats.records += 1;
+            match self.scan_record(record, record_position) {
+                Ok(output) => output,
+                Err(KeyRemoverError::MalformedJson { message, offset, column, .. }) => {
+                    let err = KeyRemoverError::MalformedJson {
+                        message,
+                        offset: record_position.offset + offset,
+                        line: record_position.line,
+                        column,
                     };
-                    let skip_end_chr_index = match messages[1] {
-                        Message::SkipEndTo(chr_index) => {
-                            (chr_index.0 - self.first_buffer_index, chr_index.1)
+                    match bad_record_policy {
+                        BadRecordPolicy::Fail => return Err(err),
+                        BadRecordPolicy::Skip => {
+                            self.stats.bad_records.push(BadRecord { line: record_position.line, message: err.to_string() });
+                            return Ok(())
                         },
-                        Message::SkipEndPreviousTo(chr_index) => {
-                            let chr_index = self.previous_chr_index(&chr_index);
-                            (chr_index.0 - self.first_buffer_index, chr_index.1)
+                        BadRecordPolicy::PassThrough => {
+                            self.stats.bad_records.push(BadRecord { line: record_position.line, message: err.to_string() });
+                            if dry_run {
+                                return Ok(())
+                            }
+                            record.to_vec()
                         },
-                        _ => panic!("Error 3"),
-                    };
-
-                    if skip_start_chr_index.0 < skip_end_chr_index.0 {
-                        let buffer_index = skip_end_chr_index.0;
-                        let buffer = &mut self.buffer_queue[buffer_index];
-                        let buffer_length = &mut self.buffer_length_queue[buffer_index];
-                        buffer.drain(..=skip_end_chr_index.1);
-                        *buffer_length -= skip_end_chr_index.1 + 1;
-                        // middle buffer
-                        for buffer_index in skip_start_chr_index.0+1..skip_end_chr_index.0 {
-                            let buffer = &mut self.buffer_queue[buffer_index];
-                            let buffer_length = &mut self.buffer_length_queue[buffer_index];
-                            buffer.clear();
-                            *buffer_length = 0;
-                        }
-                        // first buffer
-                        let buffer_index = skip_start_chr_index.0;
-                        let buffer = &mut self.buffer_queue[buffer_index];
-                        let buffer_length = &mut self.buffer_length_queue[buffer_index];
-                        buffer.drain(skip_start_chr_index.1..);
-                        *buffer_length = skip_start_chr_index.1;
-                    } else { // if skip_start_chr == skip_end_chr
-                        let buffer_index = skip_start_chr_index.0;
-                        let buffer = &mut self.buffer_queue[buffer_index];
-                        let buffer_length = &mut self.buffer_length_queue[buffer_index];
-                        buffer.drain(skip_start_chr_index.1..=skip_end_chr_index.1);
-                        *buffer_length -= skip_end_chr_index.1 - skip_start_chr_index.1 + 1;
                     }
-                });
-
-                // (3) Write buffers
-                let count_of_buffer_to_write = last_buffer_index_to_write - self.first_buffer_index + 1;
-                for buffer_index in 0..count_of_buffer_to_write {
-                    let buffer = &self.buffer_queue[buffer_index];
-                    let length = self.buffer_length_queue[buffer_index];
-                    writer.write_all(&buffer[..length]).unwrap();
-                };
-                self.buffer_queue.drain(..count_of_buffer_to_write);
-                self.buffer_length_queue.drain(..count_of_buffer_to_write);
-                self.first_buffer_index += count_of_buffer_to_write;
-            }
-
-            // (4) Load next buffer
-            let mut next_buffer = vec![0; self.buffer_size];
-            filled_byte_size = reader.read(&mut next_buffer).unwrap(); // TODO: Deal error
-            self.buffer_length_queue.push(filled_byte_size);
-            self.scanner.process_new_buffer(&next_buffer[..filled_byte_size]);
-            self.buffer_queue.push(next_buffer);
-        }
-
-        writer.flush().unwrap();
-    }
-    fn get_message_end_index(&self, last_buffer_index_to_write: usize) -> usize {
-        let mut message_end_index = 0;
-        for message in self.message_queue.iter() {
-            let chr_index = match &message {
-                Message::SkipStartFrom(v) => v,
-                Message::SkipEndTo(v) => v,
-                Message::SkipEndPreviousTo(v) => v,
-            };
-            if chr_index.0 > last_buffer_index_to_write {
+                },
+                Err(err) => return Err(err),
+            }
+        };
+
+        // (2) Write
+        self.write_removed_lines()?;
+        writer.write_all(&output)?;
+        self.stats.bytes_written += output.len();
+        if has_newline && !dry_run {
+            writer.write_all(b"\n")?;
+            self.stats.bytes_written += 1;
+        }
+        Ok(())
+    }
+    // Reset the scan and write state for the next document or record
+    fn reset_state(&mut self) {
+        self.scanner.reset();
+        self.first_buffer_index = 0;
+        self.buffer_queue.clear();
+        self.buffer_length_queue.clear();
+        self.message_queue.clear();
+        self.mode = Mode::Remain;
+        self.written_chr_index = (0, 0);
+        if let Some(formatter) = self.formatter.as_mut() {
+            formatter.reset();
+        }
+    }
+    // Scan the record as a whole document with the reset state
+    fn scan_record(&mut self, record: &[u8], record_position: &RecordPosition) -> Result<Vec<u8>, KeyRemoverError> {
+        self.reset_state();
+        if let Some(match_recorder) = self.scanner.match_recorder.as_mut() {
+            match_recorder.set_record(record_position.offset, record_position.line);
+        }
+
+        let bytes_written = self.stats.bytes_written;
+        let mut output = Vec::with_capacity(record.len());
+        self.scanner.process_new_buffer(record)?;
+        self.buffer_queue.push_back(record.to_vec());
+        self.buffer_length_queue.push_back(record.len());
+        self.scanner.finish()?;
+        self.write_resolved_data(&mut output)?;
+        // Counted when the output is written
+        self.stats.bytes_written = bytes_written;
+        self.stats.add_scan_stats(std::mem::take(&mut self.scanner.stats));
+        Ok(output)
+    }
+    fn write_resolved_data<W: Write>(&mut self, writer: &mut W) -> Result<(), KeyRemoverError> {
+        // (1) Pull out messages
+        self.message_queue.extend(self.scanner.resolved_messages());
+
+        // (2) Get end of resolved data
+        let mut resolved_end = match self.scanner.resolved_until() {
+            Some(chr_index) => self.normalized_chr_index(chr_index),
+            None => (self.scanner.next_buffer_index, 0),
+        };
+        if self.drain_writer {
+            // Only whole buffers are transformed
+            resolved_end.1 = 0;
+        }
+
+        // (3) Collect pieces of data between messages
+        let mut pieces = Vec::new();
+        let mut message_count = 0;
+        while message_count < self.message_queue.len() {
+            let message = &self.message_queue[message_count];
+            let boundary = self.normalized_chr_index(message.boundary());
+            if boundary > resolved_end {
                 break
             }
-            message_end_index += 1;
+            if boundary < self.written_chr_index {
+                return Err(KeyRemoverError::Internal(format!(
+                    "Message {:?} is before the written index {:?}", message, self.written_chr_index,
+                )))
+            }
+            let is_skip_start = matches!(message, Message::SkipStartFrom(_) | Message::ReplaceFrom(_, _));
+            if let Mode::Remain = self.mode {
+                self.push_range_pieces(&mut pieces, self.written_chr_index, boundary);
+            }
+            if let Message::ReplaceFrom(_, _) = message {
+                pieces.push(Piece::Replacement(message_count));
+            }
+            self.mode = if is_skip_start { Mode::Skip } else { Mode::Remain };
+            self.written_chr_index = boundary;
+            message_count += 1;
         }
-        message_end_index
+        if let Mode::Remain = self.mode {
+            self.push_range_pieces(&mut pieces, self.written_chr_index, resolved_end);
+        }
+        self.written_chr_index = resolved_end;
+
+        // (4) Write pieces without copying
+        let mut slices: Vec<IoSlice> = pieces.iter().map(|piece| {
+            match piece {
+                Piece::Range(queue_index, from, to) => IoSlice::new(&self.buffer_queue[*queue_index][*from..*to]),
+                Piece::Replacement(message_index) => match &self.message_queue[*message_index] {
+                    Message::ReplaceFrom(_, replacement) => IoSlice::new(replacement),
+                    _ => IoSlice::new(&[]),
+                },
+            }
+        }).collect();
+        let removals = self.scanner.match_recorder.as_mut().map(MatchRecorder::take_matches).unwrap_or_default();
+        if let Some(removed_lines) = self.removed_lines.as_mut() {
+            for removal in &removals {
+                removed_lines.extend_from_slice(&removal.to_removed_line());
+            }
+        }
+        if self.dry_run {
+            // Removals are written instead of the data
+            let match_lines: String = removals.iter().map(RemovalMatch::to_json_line).collect();
+            self.stats.bytes_written += match_lines.len();
+            writer.write_all(match_lines.as_bytes())?;
+        } else if let Some(formatter) = self.formatter.as_mut() {
+            let mut formatted = Vec::new();
+            for slice in &slices {
+                formatter.format(slice, &mut formatted);
+            }
+            self.stats.bytes_written += formatted.len();
+            writer.write_all(&formatted)?;
+        } else if self.drain_writer {
+            drop(slices);
+            let count_of_written_buffer = self.written_chr_index.0 - self.first_buffer_index;
+            self.stats.bytes_written += write_by_drain(
+                writer,
+                self.buffer_queue.range_mut(..count_of_written_buffer),
+                &self.buffer_length_queue,
+                &self.message_queue,
+                &pieces,
+            )?;
+        } else {
+            self.stats.bytes_written += slices.iter().map(|slice| slice.len()).sum::<usize>();
+            write_all_vectored(writer, &mut slices)?;
+        }
+        self.message_queue.drain(..message_count);
+
+        // (5) Release written buffers to reuse
+        let count_of_written_buffer = self.written_chr_index.0 - self.first_buffer_index;
+        for buffer in self.buffer_queue.drain(..count_of_written_buffer) {
+            if !self.drain_writer && buffer.len() == self.buffer_size && self.free_buffers.len() < MAX_FREE_BUFFERS {
+                self.free_buffers.push(buffer);
+            }
+        }
+        self.buffer_length_queue.drain(..count_of_written_buffer);
+        self.first_buffer_index = self.written_chr_index.0;
+
+        // (6) Release held buffers of which data is skipped
+        //  - Their lengths are kept to index the rest
+        let skip_start = if let Mode::Skip = self.mode { Some(self.written_chr_index.0) } else { None };
+        for skipped_buffers in self.scanner.skipped_buffers(skip_start) {
+            for buffer_index in skipped_buffers.start.max(self.first_buffer_index)..skipped_buffers.end {
+                let buffer = std::mem::take(&mut self.buffer_queue[buffer_index - self.first_buffer_index]);
+                if buffer.len() == self.buffer_size && self.free_buffers.len() < MAX_FREE_BUFFERS {
+                    self.free_buffers.push(buffer);
+                }
+            }
+        }
+        self.peak_held_bytes = self.peak_held_bytes.max(self.buffer_queue.iter().map(Vec::capacity).sum());
+        Ok(())
     }
-    fn get_optional_skip_end_index(&self, message: &Message) -> Option<ChrIndex> {
-        match message {
-            Message::SkipStartFrom(_) => {
-                None
-            },
-            Message::SkipEndTo(chr_index) => {
-                Some(chr_index.clone())
+    // Workers keep the removals to write them in order
+    fn write_removed_lines(&mut self) -> io::Result<()> {
+        if let (Some(removed_out), Some(removed_lines)) = (self.removed_out.as_mut(), self.removed_lines.as_mut()) {
+            removed_out.0.write_all(removed_lines)?;
+            removed_lines.clear();
+        }
+        Ok(())
+    }
+    // Pieces of [start, end)
+    fn push_range_pieces(&self, pieces: &mut Vec<Piece>, start: ChrIndex, end: ChrIndex) {
+        for buffer_index in start.0..=end.0 {
+            let queue_index = buffer_index - self.first_buffer_index;
+            if queue_index == self.buffer_queue.len() {
+                break
+            }
+            let from = if buffer_index == start.0 { start.1 } else { 0 };
+            let to = if buffer_index == end.0 { end.1 } else { self.buffer_length_queue[queue_index] };
+            if from < to {
+                pieces.push(Piece::Range(queue_index, from, to));
+            }
+        }
+    }
+    // The end of buffer is the start of next buffer
+    fn normalized_chr_index(&self, chr_index: ChrIndex) -> ChrIndex {
+        if chr_index.0 < self.first_buffer_index {
+            // End of the released buffer
+            return (self.first_buffer_index, 0)
+        }
+        let queue_index = chr_index.0 - self.first_buffer_index;
+        match self.buffer_length_queue.get(queue_index) {
+            Some(length) if chr_index.1 >= *length => (chr_index.0 + 1, 0),
+            _ => chr_index,
+        }
+    }
+}
+
+// Start of the record in NDJSON stream
+#[derive(Debug, Clone, Copy)]
+struct RecordPosition {
+    line: usize,
+    offset: usize,
+}
+impl Default for RecordPosition {
+    fn default() -> Self {
+        Self { line: 1, offset: 0 }
+    }
+}
+
+impl FromStr for BadRecordPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "pass" | "pass-through" => Ok(Self::PassThrough),
+            _ => Err(format!("Unknown policy for bad record (fail, skip or pass-through): {}", s)),
+        }
+    }
+}
+
+// Data to write
+#[derive(Debug)]
+enum Piece {
+    Range(usize, usize, usize), // Index of buffer queue, start and end
+    Replacement(usize), // Index of message queue
+}
+
+// Write::write_all_vectored is not stable
+fn write_all_vectored<W: Write>(writer: &mut W, mut slices: &mut [IoSlice]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(written_byte_size) => IoSlice::advance_slices(&mut slices, written_byte_size),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Old write path which replaces the skipped ranges of each written buffer and writes the buffers one by one
+//  - Replacement is put after the data kept before it
+fn write_by_drain<'a, W: Write>(
+    writer: &mut W,
+    buffers: impl Iterator<Item = &'a mut Vec<u8>>,
+    buffer_lengths: &VecDeque<usize>,
+    messages: &VecDeque<Message>,
+    pieces: &[Piece],
+) -> io::Result<usize> {
+    // (1) Ranges of each buffer to replace: start, end and replacement
+    let mut edits: Vec<Vec<(usize, usize, Vec<u8>)>> = Vec::new();
+    let mut cursor = 0; // Position in the last buffer of the edits
+    let mut replacement = Vec::new();
+    for piece in pieces {
+        match piece {
+            Piece::Range(queue_index, from, to) => {
+                while edits.len() <= *queue_index {
+                    if let Some(last_index) = edits.len().checked_sub(1) {
+                        edits[last_index].push((cursor, buffer_lengths[last_index], std::mem::take(&mut replacement)));
+                    }
+                    edits.push(Vec::new());
+                    cursor = 0;
+                }
+                edits[*queue_index].push((cursor, *from, std::mem::take(&mut replacement)));
+                cursor = *to;
             },
-            Message::SkipEndPreviousTo(chr_index) => {
-                Some(self.previous_chr_index(chr_index))
+            Piece::Replacement(message_index) => if let Message::ReplaceFrom(_, bytes) = &messages[*message_index] {
+                replacement.extend_from_slice(bytes);
             },
         }
     }
-    fn previous_chr_index(&self, chr_index: &ChrIndex) -> ChrIndex {
-        if chr_index.1 == 0 {
-            let previous_buffer_index = chr_index.0 - 1;
-            let buffer_queue_index = previous_buffer_index - self.first_buffer_index;
-            (chr_index.0-1, self.buffer_length_queue[buffer_queue_index] - 1)
-        } else {
-            (chr_index.0, chr_index.1-1)
+    if let Some(last_index) = edits.len().checked_sub(1) {
+        edits[last_index].push((cursor, buffer_lengths[last_index], std::mem::take(&mut replacement)));
+    }
+
+    // (2) Transform and write each buffer
+    //  - Buffer without edits is skipped as a whole
+    //  - Replacement without kept data is written after the buffers
+    let mut bytes_written = 0;
+    for (queue_index, buffer) in buffers.enumerate() {
+        buffer.truncate(buffer_lengths[queue_index]);
+        match edits.get(queue_index) {
+            Some(buffer_edits) => for (start, end, bytes) in buffer_edits.iter().rev() {
+                let end = (*end).min(buffer.len());
+                buffer.splice((*start).min(end)..end, bytes.iter().copied());
+            },
+            None => buffer.clear(),
         }
+        bytes_written += buffer.len();
+        writer.write_all(buffer)?;
     }
+    bytes_written += replacement.len();
+    writer.write_all(&replacement)?;
+    Ok(bytes_written)
 }
 
 #[derive(Debug)]
@@ -242,6 +745,7 @@ enum Mode {
 #[allow(dead_code)]
 mod tests {
     use std::io::Cursor;
+    use std::num::NonZeroUsize;
 
     use super::*;
 
@@ -287,11 +791,1382 @@ mod tests {
 
         let input = Cursor::new(sample_json_string);
         let mut output = Vec::new();
-        key_remover.process(input, &mut output);
+        key_remover.process(input, &mut output).unwrap();
 
         println!("# Input");
         println!("{:?}", cloned_input_string);
         println!("# Output");
         println!("{:?}", String::from_utf8(output).unwrap());
     }
-}
\ No newline at end of file
+
+    fn remove_keys(input: &str, keys_to_remove: &[&str], buffer_size: usize) -> String {
+        let keys_to_remove = keys_to_remove.iter().map(|key| key.to_string()).collect();
+        let mut key_remover = KeyRemover::init(buffer_size, keys_to_remove);
+
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input.to_string()), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // Every split point of buffers has to give the same result
+    fn assert_removed_for_all_buffer_sizes(input: &str, keys_to_remove: &[&str], expected: &str) {
+        for buffer_size in 1..=input.len()+1 {
+            let output = remove_keys(input, keys_to_remove, buffer_size);
+            assert_eq!(output, expected, "buffer size: {}", buffer_size);
+        }
+    }
+
+    #[test]
+    fn test_remove_keys_at_each_position() {
+        let input = r#"{"a":1,"b":2,"c":3}"#;
+        assert_removed_for_all_buffer_sizes(input, &["a"], r#"{"b":2,"c":3}"#);
+        assert_removed_for_all_buffer_sizes(input, &["b"], r#"{"a":1,"c":3}"#);
+        assert_removed_for_all_buffer_sizes(input, &["c"], r#"{"a":1,"b":2}"#);
+        assert_removed_for_all_buffer_sizes(input, &["a", "b", "c"], r#"{}"#);
+
+        // Only key, last key after nested object and consecutive keys
+        assert_removed_for_all_buffer_sizes(r#"{ "a" : "x" }"#, &["a"], r#"{  }"#);
+        assert_removed_for_all_buffer_sizes(r#"{"b":{"c":1} , "a":[1]}"#, &["a"], r#"{"b":{"c":1} }"#);
+        assert_removed_for_all_buffer_sizes(r#"{"a":1,"b":2,"c":3,"d":4}"#, &["b", "c"], r#"{"a":1,"d":4}"#);
+        assert_removed_for_all_buffer_sizes(r#"{"a":{"x":1},"b":2}"#, &["a", "b"], r#"{}"#);
+    }
+
+    #[test]
+    fn test_remove_key_in_nested_array() {
+        let input = r#"{"a":{"x":[1,2]},"c":[{"a":1,"d":[{"a":null}]}]}"#;
+        assert_removed_for_all_buffer_sizes(input, &["a"], r#"{"c":[{"d":[{}]}]}"#);
+    }
+
+    #[test]
+    fn test_remove_only_member_closed_with_object() {
+        // Found by the property tests (proptest-regressions/lib.txt)
+        let input = r#"[{"": null}]"#;
+        assert_removed_for_all_buffer_sizes(input, &[""], r#"[{}]"#);
+
+        // Only member followed by the next element
+        let input = r#"[{"a": 4}, {"c": 5}, {"a": true}, {"x": {"a": null}, "c": 6}]"#;
+        assert_removed_for_all_buffer_sizes(input, &["a"], r#"[{}, {"c": 5}, {}, {"x": {}, "c": 6}]"#);
+    }
+
+    #[test]
+    fn test_comma_and_bracket_in_string_value() {
+        let input = r#"{"note": "a, \"id\": 5", "id": 1}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"note": "a, \"id\": 5"}"#);
+
+        let input = r#"{"note": "{\"id\": 5}", "id": 1}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"note": "{\"id\": 5}"}"#);
+
+        let input = r#"{"note": "[{,\"id\":[", "id": {"id": 1}}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"note": "[{,\"id\":["}"#);
+    }
+
+    #[test]
+    fn test_escaped_backslash_before_quote() {
+        let input = r#"{"path": "C:\\", "id": 1, "x": "\\\", \"id\": 2"}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"path": "C:\\", "x": "\\\", \"id\": 2"}"#);
+    }
+
+    #[test]
+    fn test_string_elements_are_not_keys() {
+        let input = r#"{"tags": ["id", "x, \"id\""], "id": 1}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"tags": ["id", "x, \"id\""]}"#);
+    }
+
+    #[test]
+    fn test_key_containing_escaped_quote() {
+        let input = r#"{"a\", \"id": 1, "id": 2}"#;
+        assert_removed_for_all_buffer_sizes(input, &["id"], r#"{"a\", \"id": 1}"#);
+    }
+
+    #[test]
+    fn test_brackets_in_string_of_removed_value() {
+        let input = r#"{"k": {"a": "}"}, "b": 1}"#;
+        assert_removed_for_all_buffer_sizes(input, &["k"], r#"{ "b": 1}"#);
+
+        let input = r#"{"b": 1, "k": ["]", "\"]", {"c": "[{"}], "d": 2}"#;
+        assert_removed_for_all_buffer_sizes(input, &["k"], r#"{"b": 1, "d": 2}"#);
+
+        let input = r#"{"k": {"a": "\\", "b": "{\"}\""}, "c": [1]}"#;
+        assert_removed_for_all_buffer_sizes(input, &["k"], r#"{ "c": [1]}"#);
+    }
+
+    fn remove_paths(input: &str, paths: &[&str], buffer_size: usize) -> String {
+        let selectors = paths.iter().map(|path| Selector::parse(path).unwrap()).collect();
+        let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors);
+
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input.to_string()), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        for path in ["$.meta.debug", "$.items[*].internal", "$..secret", "$.a[0]..*", "$..[*].b"] {
+            assert_eq!(Selector::parse(path).unwrap().to_string(), path);
+        }
+        assert_eq!(Selector::parse("$['a.b'][\"c\"]").unwrap().to_string(), "$['a.b'].c");
+        for path in ["$['a.b']..['*'].c", "$..[\"it's\"]['x[0]']", "$['']", "$['it\\'s \\\\ \"q\"']", "$[**].id", "$.a..^x-.*$", "$..['^x']", "*.bak"] {
+            let selector = Selector::parse(path).unwrap();
+            assert_eq!(selector.to_string(), path);
+            assert_eq!(Selector::parse(&selector.to_string()).unwrap(), selector);
+        }
+        assert_eq!(Selector::parse("secret").unwrap(), Selector::parse("$..secret").unwrap());
+        assert_eq!(Selector::parse("$[**].id").unwrap(), Selector::record_key("id"));
+        assert_eq!(Selector::parse("$..^x-.*$").unwrap(), Selector::key_pattern("^x-.*$").unwrap());
+        for path in ["$", "$.a[0]", "$.", "$.a[", "$.a[x]", "$.a['b]", "$a", "$[**]", "$.a[**]", "$[**][0].a", "$[**]..a", "$[**][**].a", "$.^x-("] {
+            assert!(Selector::parse(path).is_err(), "{}", path);
+        }
+
+        // List is split out of the brackets and quoted keys
+        assert_eq!(split_outside_brackets("$['a,b'].c,$..d,e", ','), vec!["$['a,b'].c", "$..d", "e"]);
+        assert_eq!(split_outside_brackets("$['it\\'s,]'],[\"x,\"],y", ','), vec!["$['it\\'s,]']", "[\"x,\"]", "y"]);
+        assert_eq!(split_outside_brackets("a", ','), vec!["a"]);
+        assert_eq!(split_once_outside_brackets("$['a=b']=c=1", '='), Some(("$['a=b']", "c=1")));
+        assert_eq!(split_once_outside_brackets("$['a=b']", '='), None);
+    }
+
+    #[test]
+    fn test_remove_by_path() {
+        let input = r#"{"id":0,"meta":{"id":1,"debug":true},"items":[{"id":2,"internal":"x"},{"internal":[],"id":3}],"debug":"root"}"#;
+        for buffer_size in 1..=input.len() {
+            let output = remove_paths(input, &["$.meta.debug"], buffer_size);
+            assert_eq!(output, r#"{"id":0,"meta":{"id":1},"items":[{"id":2,"internal":"x"},{"internal":[],"id":3}],"debug":"root"}"#);
+            let output = remove_paths(input, &["$.items[*].internal"], buffer_size);
+            assert_eq!(output, r#"{"id":0,"meta":{"id":1,"debug":true},"items":[{"id":2},{"id":3}],"debug":"root"}"#);
+            let output = remove_paths(input, &["$.items[1].id", "$.id"], buffer_size);
+            assert_eq!(output, r#"{"meta":{"id":1,"debug":true},"items":[{"id":2,"internal":"x"},{"internal":[]}],"debug":"root"}"#);
+            let output = remove_paths(input, &["$..id"], buffer_size);
+            assert_eq!(output, r#"{"meta":{"debug":true},"items":[{"internal":"x"},{"internal":[]}],"debug":"root"}"#);
+            let output = remove_paths(input, &["$.items..*"], buffer_size);
+            assert_eq!(output, r#"{"id":0,"meta":{"id":1,"debug":true},"items":[{},{}],"debug":"root"}"#);
+        }
+    }
+
+    fn keep_paths(input: &str, paths: &[&str], buffer_size: usize, prune_empty: bool) -> String {
+        let selectors = paths.iter().map(|path| {
+            if path.starts_with('$') { Selector::parse(path).unwrap() } else { Selector::record_key(path) }
+        }).collect();
+        let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors)
+            .mode(FilterMode::Keep)
+            .prune_empty(prune_empty);
+
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input.to_string()), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_keep_only_selected_keys() {
+        let input = r#"{"id":1,"x":{"id":2},"name":"a","meta":{"y":[4],"version":3},"items":[{"name":"b"}],"user":"u"}"#;
+        for buffer_size in 1..=input.len() {
+            for prune_empty in [false, true] {
+                let output = keep_paths(input, &["id", "name", "$.meta.version", "$.user.name"], buffer_size, prune_empty);
+                assert_eq!(output, r#"{"id":1,"name":"a","meta":{"version":3}}"#);
+                let output = keep_paths(input, &["$.x", "$.items[*].name"], buffer_size, prune_empty);
+                assert_eq!(output, r#"{"x":{"id":2},"items":[{"name":"b"}]}"#);
+            }
+            let output = keep_paths(input, &["$..id"], buffer_size, false);
+            assert_eq!(output, r#"{"id":1,"x":{"id":2},"meta":{"y":[]},"items":[{}]}"#);
+            let output = keep_paths(input, &["$..id"], buffer_size, true);
+            assert_eq!(output, r#"{"id":1,"x":{"id":2}}"#);
+        }
+
+        // Scalar values are removed from the ancestors and the containers without the selected member are left empty unless pruned
+        let input = r#"{"id":1,"ssn_list":["123-45-6789"],"meta":{"tokens":["abc"],"id":2},"list":[3,{"id":4},"x",[{"id":5},{}],{"y":6}]}"#;
+        for buffer_size in 1..=input.len() {
+            let output = keep_paths(input, &["$..id"], buffer_size, false);
+            assert_eq!(output, r#"{"id":1,"ssn_list":[],"meta":{"tokens":[],"id":2},"list":[{"id":4},[{"id":5},{}],{}]}"#);
+            let output = keep_paths(input, &["$..id"], buffer_size, true);
+            assert_eq!(output, r#"{"id":1,"meta":{"id":2},"list":[{"id":4},[{"id":5}]]}"#);
+        }
+
+        let input = r#"[{"id":1,"x":2},{"name":"n","id":[1,{"x":1}],"z":{"name":0}}]"#;
+        for buffer_size in 1..=input.len() {
+            for prune_empty in [false, true] {
+                let output = keep_paths(input, &["id", "name"], buffer_size, prune_empty);
+                assert_eq!(output, r#"[{"id":1},{"name":"n","id":[1,{"x":1}]}]"#);
+            }
+        }
+
+        // Container is not held unless pruned
+        let mut input = String::from(r#"{"id":1,"data":["#);
+        for idx in 0..20_000 {
+            input.push_str(&format!(r#"{{"x":"{}","y":[1,{{"z":2}}]}},"#, idx));
+        }
+        input.push_str(r#"{"id":2}]}"#);
+        let selectors = vec![Selector::parse("$..id").unwrap()];
+        let mut key_remover = KeyRemover::init_with_selectors(64, selectors.clone()).mode(FilterMode::Keep);
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(&input), &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(r#"{"y":[{}]},{"y":[{}]},{"id":2}]}"#));
+        assert!(key_remover.peak_held_bytes <= 4 * 64, "{}", key_remover.peak_held_bytes);
+        let mut key_remover = KeyRemover::init_with_selectors(64, selectors).mode(FilterMode::Keep).prune_empty(true);
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(&input), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), r#"{"id":1,"data":[{"id":2}]}"#);
+        assert!(key_remover.peak_held_bytes >= input.len() - 64);
+    }
+
+    #[test]
+    fn test_redact_value() {
+        let input = r#"{"password": "hunter2", "user": {"password": {"x": "}"}, "pin": 1234}, "pin":[1,2] , "id": 1}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["id".to_string()])
+                .redact(Selector::key("password"), b"\"***\"".to_vec())
+                .redact(Selector::parse("$..pin").unwrap(), b"null".to_vec());
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input.to_string()), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                r#"{"password": "***", "user": {"password": "***", "pin": null}, "pin":null }"#,
+            );
+        }
+    }
+
+    #[test]
+    fn test_replacement_to_json() {
+        let cases = [
+            ("null", "null"),
+            ("-1.5e3", "-1.5e3"),
+            (r#""***""#, r#""***""#),
+            (r#"{"a": [1]}"#, r#"{"a": [1]}"#),
+            ("***", r#""***""#),
+            ("{x}", r#""{x}""#),
+            ("[1,]", r#""[1,]""#),
+            (r#""a"b""#, r#""\"a\"b\"""#),
+            ("01", r#""01""#),
+            ("a\\b\tc", r#""a\\b\tc""#),
+        ];
+        for (replacement, expected) in cases {
+            assert_eq!(String::from_utf8(replacement_to_json(replacement)).unwrap(), expected, "{}", replacement);
+        }
+    }
+
+    #[test]
+    fn test_rename_key() {
+        let input = r#"{"userId": 1, "meta": {"userId": [2], "drop": 0, "userName": "a"}, "secret": "s"}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["drop".to_string()])
+                .redact(Selector::key("secret"), b"null".to_vec())
+                .rename("userId".to_string(), "user_id")
+                .rename("userName".to_string(), "user \"name\"")
+                .rename("secret".to_string(), "hidden");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input.to_string()), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                r#"{"user_id": 1, "meta": {"user_id": [2], "user \"name\"": "a"}, "hidden": null}"#,
+            );
+        }
+
+        // Container kept to find the selected member or to check the value is renamed
+        let input = r#"{"x": {"id": 1}, "n": {"a": 1}, "id": 2}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init_with_selectors(buffer_size, vec![Selector::parse("$..id").unwrap(), Selector::key("n")])
+                .mode(FilterMode::Keep)
+                .rename("x".to_string(), "X");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), r#"{"X": {"id": 1}, "n": {"a": 1}, "id": 2}"#);
+
+            let mut key_remover = KeyRemover::init(buffer_size, Vec::new())
+                .remove_if(Selector::key("n"), b"0".to_vec())
+                .rename("n".to_string(), "N");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), r#"{"x": {"id": 1}, "N": {"a": 1}, "id": 2}"#);
+        }
+
+        // Ancestors of the selected member are renamed in keep mode
+        let input = r#"{"obj": {"id": 1, "x": 2}, "arr": [{"id": 3}, 4], "other": {"y": [5]}}"#;
+        let expected_outputs = [
+            (false, r#"{"o": {"id": 1}, "a": [{"id": 3}], "z": {"y": []}}"#),
+            (true, r#"{"o": {"id": 1}, "a": [{"id": 3}]}"#),
+        ];
+        for (prune_empty, expected) in expected_outputs {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["id".to_string()])
+                    .mode(FilterMode::Keep)
+                    .prune_empty(prune_empty)
+                    .rename("obj".to_string(), "o")
+                    .rename("arr".to_string(), "a")
+                    .rename("other".to_string(), "z");
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_json_error() {
+        let cases = [
+            ("{\n  \"a\" 1}", 2, 7),
+            ("{\"a\":}", 1, 6),
+            ("{\"a\": [1}", 1, 9),
+            ("[1]]", 1, 4),
+            ("{\"a\": \"x", 1, 9),
+            ("{\"b\": [{}", 1, 10),
+        ];
+        for (input, expected_line, expected_column) in cases {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]);
+                let result = key_remover.process(Cursor::new(input.to_string()), Vec::new());
+                match result {
+                    Err(KeyRemoverError::MalformedJson { line, column, offset, .. }) => {
+                        assert_eq!((line, column), (expected_line, expected_column), "{}", input);
+                        assert_eq!(offset, input.lines().take(line-1).map(|l| l.len()+1).sum::<usize>() + column - 1);
+                    },
+                    _ => panic!("{} is not error: {:?}", input, result),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let cases = [
+            ("{\"a\": tru}", 1, 10),
+            ("{\"a\": 01}", 1, 8),
+            ("{\"a\": 1.}", 1, 9),
+            ("{\"a\": -}", 1, 8),
+            ("{\"a\": 1,}", 1, 9),
+            ("[1, 2,]", 1, 7),
+            ("{\"a\": \"\\x\"}", 1, 9),
+            ("{\"a\": 1}\n{\"b\": 2}", 2, 1),
+            ("{\"a\" 1}", 1, 6),
+            ("[1 2]", 1, 4),
+            ("", 1, 1),
+            ("[1e]", 1, 4),
+        ];
+        for (input, expected_line, expected_column) in cases {
+            for buffer_size in 1..=input.len().max(1) {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]);
+                match key_remover.validate(Cursor::new(input.to_string())) {
+                    Err(KeyRemoverError::MalformedJson { line, column, .. }) => {
+                        assert_eq!((line, column), (expected_line, expected_column), "{}", input);
+                    },
+                    result => panic!("{} is not error: {:?}", input, result),
+                }
+            }
+        }
+
+        // Remover is not left in strict mode after the validation
+        let mut key_remover = KeyRemover::init(4, vec!["a".to_string()]);
+        assert!(key_remover.validate(Cursor::new("{\"a\": 1,}")).is_err());
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new("{\"a\": 1,}"), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{}");
+
+        let input = r#" {"a": [true, false, null], "b": -0.5e+10, "c": "\u00e9\n", "d": {}, "e": []} "#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]).strict(true);
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), remove_keys(input, &["a"], buffer_size));
+        }
+    }
+
+    #[test]
+    fn test_ndjson_records() {
+        let input = "{\"a\": 1, \"b\": 2}\n{\"a\": [1, \"b\": 3}\n\n[{\"a\": 4}, {\"c\": 5}]\n{\"b\": \"x";
+        let expected_outputs = [
+            (BadRecordPolicy::Skip, "{ \"b\": 2}\n\n[{}, {\"c\": 5}]\n"),
+            (BadRecordPolicy::PassThrough, "{ \"b\": 2}\n{\"a\": [1, \"b\": 3}\n\n[{}, {\"c\": 5}]\n{\"b\": \"x"),
+        ];
+        for (bad_record_policy, expected) in expected_outputs {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]).ndjson(bad_record_policy);
+                let mut output = Vec::new();
+                let stats = key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+                assert_eq!(stats.records, 4);
+                assert_eq!(stats.bad_records.iter().map(|bad_record| bad_record.line).collect::<Vec<_>>(), vec![2, 5]);
+            }
+        }
+
+        let mut key_remover = KeyRemover::init(4, vec!["a".to_string()]).ndjson(BadRecordPolicy::Fail);
+        match key_remover.process(Cursor::new(input), Vec::new()) {
+            Err(KeyRemoverError::MalformedJson { line, column, offset, .. }) => {
+                assert_eq!((line, column, offset), (2, 14, 30));
+            },
+            result => panic!("Bad record is not error: {:?}", result),
+        }
+
+        // Trailing comma, malformed literal and unclosed record are rejected without strict mode
+        let input = "{\"a\": 1,}\nnotjson\n{\"a\":\n{\"a\": 2}";
+        for strict in [false, true] {
+            for threads in [1, 2] {
+                let mut key_remover = KeyRemover::init(4, vec!["a".to_string()])
+                    .strict(strict)
+                    .ndjson(BadRecordPolicy::Skip)
+                    .threads(threads);
+                let mut output = Vec::new();
+                let stats = key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), "{}");
+                assert_eq!(stats.bad_records.iter().map(|bad_record| bad_record.line).collect::<Vec<_>>(), vec![1, 2, 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ndjson_in_parallel() {
+        let mut input = String::new();
+        for idx in 0..300 {
+            match idx % 7 {
+                3 => input.push_str(&format!("{{\"a\": [{}, \"b\": 1}}\n", idx)),
+                5 => input.push('\n'),
+                _ => input.push_str(&format!("{{\"id\": {}, \"a\": {{\"b\": \"{}\"}}, \"c\": [{{\"a\": 1}}]}}\n", idx, "x".repeat(idx % 13))),
+            }
+        }
+        input.push_str("{\"a\": 1, \"id\": 300}");
+
+        for bad_record_policy in [BadRecordPolicy::Skip, BadRecordPolicy::PassThrough] {
+            let mut key_remover = KeyRemover::init(16, vec!["a".to_string()]).ndjson(bad_record_policy);
+            let mut expected = Vec::new();
+            let expected_stats = key_remover.process(Cursor::new(&input), &mut expected).unwrap();
+            for (threads, buffer_size) in [(2, 1), (3, 16), (4, 100), (8, 4096)] {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()])
+                    .ndjson(bad_record_policy)
+                    .threads(threads);
+                let mut output = Vec::new();
+                let stats = key_remover.process(Cursor::new(&input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), String::from_utf8(expected.clone()).unwrap());
+                assert_eq!(ProcessStats { elapsed: expected_stats.elapsed, ..stats }, expected_stats);
+            }
+        }
+
+        // Output is written until the first bad record
+        let mut key_remover = KeyRemover::init(64, vec!["a".to_string()]).ndjson(BadRecordPolicy::Fail).threads(4);
+        let mut output = Vec::new();
+        match key_remover.process(Cursor::new(&input), &mut output) {
+            Err(KeyRemoverError::MalformedJson { line, .. }) => assert_eq!(line, 4),
+            result => panic!("Bad record is not error: {:?}", result),
+        }
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_fast_path_is_identical_to_scalar_path() {
+        let inputs = [
+            get_sample_json_string(),
+            r#"{"id": 1, "note": "a, \"id\": {\\", "user": {"id": [1, {"id": 2}], "password": "pA\n", "name": "n"}, "tags": ["id", "x"], "id": null}"#.to_string(),
+            "{\n  \"비밀번호\" : \"값\",\n  \"pass\\u0077ord\": [true, false, null, -1.5e3],\n  \"keep\": {\"name\": \"   \"}\n}\n".to_string(),
+            "[{\"a\": 4}, {\"c\": 5}, {\"a\": true}, {\"x\": {\"a\": null}, \"c\": 6}]".to_string(),
+            "{\"a\": [1, 2,], \"b\": \"\u{1}\"}".to_string(),
+            "{\n\"a\": \"x\n\"}".to_string(),
+            "{\"password\": 1, \"name\": \"tail".to_string(),
+        ];
+        let new_key_remover = |buffer_size, fast_path, strict| {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["id".to_string(), "key_0_1".to_string(), "a".to_string()])
+                .redact(Selector::key("password"), b"null".to_vec())
+                .rename("name".to_string(), "user_name")
+                .strict(strict);
+            key_remover.scanner.fast_path = fast_path;
+            key_remover
+        };
+        for input in &inputs {
+            for buffer_size in [1, 2, 5, 13, 64, input.len()] {
+                for strict in [false, true] {
+                    let results: Vec<(Vec<u8>, String)> = [false, true].iter().map(|fast_path| {
+                        let mut output = Vec::new();
+                        let result = new_key_remover(buffer_size, *fast_path, strict).process(Cursor::new(input), &mut output)
+                            .map(|stats| ProcessStats { elapsed: Duration::ZERO, ..stats });
+                        (output, format!("{:?}", result))
+                    }).collect();
+                    assert_eq!(results[0], results[1], "{}", input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_key_list() {
+        let input = r#"{"pii_12": 1, "pii_1": 2, "pii_123": 3, "pii_": 4, "meta": {"pii_2999": [1], "x": {"y": 5, "z": 6}}, "pii_7": 7, "": 8, "piie": 9}"#;
+        let mut keys: Vec<String> = (0..3000).map(|idx| format!("pii_{}", idx)).collect();
+        keys.retain(|key| key != "pii_12");
+        let selectors: Vec<Selector> = keys.iter().map(|key| Selector::key(key))
+            .chain([Selector::parse("$.*.x.y").unwrap()])
+            .collect();
+        let mut key_remover = KeyRemover::init_with_selectors(7, selectors.clone());
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"pii_12": 1, "pii_": 4, "meta": { "x": { "z": 6}}, "": 8, "piie": 9}"#,
+        );
+
+        // Empty key is compared without early rejection
+        let mut key_remover = KeyRemover::init_with_selectors(7, selectors).redact(Selector::key(""), b"null".to_vec());
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"pii_12": 1, "pii_": 4, "meta": { "x": { "z": 6}}, "": null, "piie": 9}"#,
+        );
+    }
+
+    #[test]
+    fn test_key_patterns() {
+        let input = r#"{"_id": 1, "id": 2, "debug_1": {"x-trace": 3, "y": 4}, "meta": {"debug_22": 5, "token_internal": 6, "x-trace": 7}, "a?": 8}"#;
+        let selectors = vec![
+            Selector::key_pattern("_*").unwrap(),
+            Selector::key_pattern("*_internal").unwrap(),
+            Selector::key_pattern("^x-.*$").unwrap(),
+            Selector::parse("$.meta.debug_?").unwrap(),
+        ];
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors.clone());
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                r#"{ "id": 2, "debug_1": { "y": 4}, "meta": {"debug_22": 5}, "a?": 8}"#,
+            );
+        }
+
+        assert_eq!(Selector::key_pattern("id").unwrap(), Selector::key("id"));
+        assert_eq!(Selector::parse("_*").unwrap(), Selector::key_pattern("_*").unwrap());
+        assert!(Selector::key_pattern("^x-(").is_err());
+    }
+
+    #[test]
+    fn test_remove_by_value() {
+        let input = r#"{"a": null, "b": "", "c": [ ], "d": {}, "e": "x", "f": [null, {"g": null, "h": {"i": 1}}], "status": "deleted", "n": 0, "s": "deleted" ,"t":null}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, Vec::new())
+                .drop_nulls()
+                .drop_empty()
+                .remove_if(Selector::key("status"), b"\"deleted\"".to_vec())
+                .remove_if(Selector::key("n"), b"0".to_vec())
+                .rename("h".to_string(), "j");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                r#"{    "e": "x", "f": [null, { "j": {"i": 1}}], "s": "deleted" }"#,
+            );
+        }
+
+        // Member of the conditioned key with a container is kept and renamed
+        let input = r#"{"n": {"a": 1}, "m": [{"n": [0]}, {"n": 0}]}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, Vec::new())
+                .remove_if(Selector::key("n"), b"0".to_vec())
+                .rename("n".to_string(), "N");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), r#"{"N": {"a": 1}, "m": [{"N": [0]}, {}]}"#);
+        }
+
+        // Values are not changed without conditions
+        let input = r#"{"a": null, "b": "", "c": {"d": [], "e": null, "n": 0}}"#;
+        let mut key_remover = KeyRemover::init(4, Vec::new())
+            .remove_if(Selector::parse("$.c.d").unwrap(), b"[ ]".to_vec())
+            .remove_if(Selector::parse("$.c.n").unwrap(), b"0".to_vec());
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), r#"{"a": null, "b": "", "c": { "e": null}}"#);
+    }
+
+    #[test]
+    fn test_remove_elements() {
+        let input = r#"{"items": [{"id": 1, "deleted": true}, {"id": 2, "deleted": false}, {"id": 3, "tags": [{"deleted": true}], "deleted": true}, {"id": 4, "name": "n", "tags": [{"deleted": true}, {"deleted": 1}]}], "other": [{"deleted": true}]}"#;
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["id".to_string()])
+                .remove_elements_if(Selector::parse("$.items[*].deleted").unwrap(), b"true".to_vec())
+                .remove_elements_if(Selector::parse("$..tags[*].deleted").unwrap(), b"true".to_vec())
+                .rename("name".to_string(), "user_name");
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                r#"{"items": [ { "deleted": false}, { "user_name": "n", "tags": [ {"deleted": 1}]}], "other": [{"deleted": true}]}"#,
+            );
+        }
+
+        // Comma is valid after the removed elements
+        let input = r#"[{"d": true}, {"d": true}, 1, {"d": true}, {"d": false}, {"d": true}]"#;
+        let mut key_remover = KeyRemover::init(2, vec!["d".to_string()])
+            .remove_elements_if(Selector::key("d"), b"true".to_vec());
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), r#"[  1, {}]"#);
+    }
+
+    #[test]
+    fn test_layout_aware() {
+        let input = "{\n  \"a\": 1,\n  \"b\": {\n    \"a\": [1, 2]\n  },\n  \"c\": \"x\", \"a\": null,\n  \"d\": [\n    {\"deleted\": true},\n    {\"deleted\": false},\n    {\"deleted\": true}\n  ],\n  \"a\": true\n}\n";
+        let expected = "{\n  \"b\": {},\n  \"c\": \"x\",\n  \"d\": [\n    {\"deleted\": false}\n  ]\n}\n";
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()])
+                .remove_elements_if(Selector::key("deleted"), b"true".to_vec())
+                .layout_aware(true);
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+
+        // Comma first style, only member and all members
+        let inputs_and_outputs = [
+            ("{ \"x\": 1\n, \"a\": 2\n, \"y\": 3\n}\n", "{ \"x\": 1\n, \"y\": 3\n}\n"),
+            ("{\n  \"a\": 1\n}", "{}"),
+            ("[\n  {\n    \"a\": 1,\n    \"a\": [\n      2\n    ]\n  },\n  { }\n]", "[\n  {},\n  { }\n]"),
+        ];
+        for (input, expected) in inputs_and_outputs {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]).layout_aware(true);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+            }
+        }
+
+        // Buffers of the value removed in the container left empty are not held until its closer
+        let input = format!("[\n  {{\n    \"a\": \"{}\"\n  }}\n]", "x".repeat(20_000));
+        let mut key_remover = KeyRemover::init(64, vec!["a".to_string()]).layout_aware(true);
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(&input), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "[\n  {}\n]");
+        assert!(key_remover.peak_held_bytes <= 4 * 64, "{}", key_remover.peak_held_bytes);
+
+        // Redacted empty container is replaced as a whole
+        for input in ["{\"a\": {}}", "{\"a\": [ ]}", "{\"a\": {\n}}"] {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, Vec::new())
+                    .redact(Selector::key("a"), b"null".to_vec())
+                    .layout_aware(true);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), "{\"a\": null}", "{:?}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_format() {
+        let indent = OutputFormat::Indent(NonZeroUsize::new(2).unwrap());
+        let input = "{\"a\": 1, \"b\": {\"c\": [1, 2, { }], \"d\": [ ], \"e\": \"x y\\\" }\"}, \"f\": {\"a\": null}}\n";
+        let expected_outputs = [
+            (OutputFormat::Minify, "{\"b\":{\"c\":[1,2,{}],\"d\":[],\"e\":\"x y\\\" }\"},\"f\":{}}\n"),
+            (indent, "{\n  \"b\": {\n    \"c\": [\n      1,\n      2,\n      {}\n    ],\n    \"d\": [],\n    \"e\": \"x y\\\" }\"\n  },\n  \"f\": {}\n}\n"),
+        ];
+        for (output_format, expected) in expected_outputs {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]).output_format(output_format);
+                let mut output = Vec::new();
+                let stats = key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+                assert_eq!(stats.bytes_written, expected.len());
+            }
+        }
+
+        // Newline after the root value only if the input has it
+        let inputs_and_outputs = [
+            (indent, "  \"str\"  ", "\"str\""),
+            (indent, " 12 \n", "12\n"),
+            (indent, "[1] {\"a\": 2}", "[\n  1\n]\n{}"),
+            (OutputFormat::Minify, "{\"b\": 1}  ", "{\"b\":1}"),
+        ];
+        for (output_format, input, expected) in inputs_and_outputs {
+            for buffer_size in 1..=input.len() {
+                let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()]).output_format(output_format);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+            }
+        }
+
+        // Each record is minified in NDJSON mode
+        let input = "{\"a\": 1, \"b\": [1, 2]} \n\n[ true ]";
+        let mut key_remover = KeyRemover::init(4, vec!["a".to_string()])
+            .ndjson(BadRecordPolicy::Fail)
+            .output_format(OutputFormat::Minify);
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(input), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"b\":[1,2]}\n\n[true]");
+    }
+
+    #[test]
+    fn test_key_normalization() {
+        let input = r#"{"Password": 1, "PASSWORD": 2, "user-id": 3, "userId": 4, "UserID": 5, "user_id": 6, "ÄPFEL": 7, "x-Trace": 8, "name": 9}"#;
+        let new_key_remover = |key_normalization| {
+            let selectors = vec![
+                Selector::key("password"),
+                Selector::key("user_id"),
+                Selector::key("äpfel"),
+                Selector::key_pattern("^x_trace$").unwrap(),
+            ];
+            KeyRemover::init_with_selectors(7, selectors).key_normalization(key_normalization)
+        };
+        let expected_outputs = [
+            (KeyNormalization::default(), r#"{"Password": 1, "PASSWORD": 2, "user-id": 3, "userId": 4, "UserID": 5, "ÄPFEL": 7, "x-Trace": 8, "name": 9}"#),
+            (KeyNormalization { ignore_case: true, ..Default::default() }, r#"{  "user-id": 3, "userId": 4, "UserID": 5, "x-Trace": 8, "name": 9}"#),
+            (KeyNormalization { unify_separators: true, ..Default::default() }, r#"{"Password": 1, "PASSWORD": 2, "userId": 4, "UserID": 5, "ÄPFEL": 7, "x-Trace": 8, "name": 9}"#),
+            (KeyNormalization { ignore_case: true, unify_separators: true, split_camel_case: true }, r#"{        "name": 9}"#),
+        ];
+        for (key_normalization, expected) in expected_outputs {
+            let mut output = Vec::new();
+            new_key_remover(key_normalization).process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected, "{:?}", key_normalization);
+        }
+
+        // Literal "-" in regex is unified while the range in class is kept
+        let input = r#"{"user-id": 1, "user_id": 2, "userId": 3, "x-a": 4, "x_b": 5, "x-1": 6, "name": 7}"#;
+        let expected_outputs = [
+            (KeyNormalization { unify_separators: true, ..Default::default() }, r#"{  "userId": 3, "x-1": 6, "name": 7}"#),
+            (KeyNormalization { unify_separators: true, split_camel_case: true, ..Default::default() }, r#"{     "x-1": 6, "name": 7}"#),
+        ];
+        for (key_normalization, expected) in expected_outputs {
+            for buffer_size in 1..=input.len() {
+                let selectors = vec![
+                    Selector::key_pattern("^user-id$").unwrap(),
+                    Selector::key_pattern(r"^(?P<prefix>x)\-[a-z]$").unwrap(),
+                ];
+                let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors).key_normalization(key_normalization);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(input), &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), expected, "{:?}", key_normalization);
+            }
+        }
+
+        // Renamed with the normalized key
+        let mut key_remover = KeyRemover::init(4, Vec::new())
+            .rename("user_name".to_string(), "name")
+            .key_normalization(KeyNormalization { split_camel_case: true, ..Default::default() });
+        let mut output = Vec::new();
+        key_remover.process(Cursor::new(r#"{"userName": "a", "USER_NAME": "b"}"#), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), r#"{"name": "a", "name": "b"}"#);
+    }
+
+    #[test]
+    fn test_stats_and_io_error() {
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disconnected"))
+            }
+        }
+        let mut key_remover = KeyRemover::init(4, vec!["a".to_string()]);
+        let result = key_remover.process(FailingReader, Vec::new());
+        assert!(matches!(result, Err(KeyRemoverError::Io(_))));
+
+        let input = r#"{"a":1,"b":2}"#;
+        let mut key_remover = KeyRemover::init(4, vec!["a".to_string()]);
+        let stats = key_remover.process(Cursor::new(input), Vec::new()).unwrap();
+        assert_eq!(stats, ProcessStats {
+            bytes_read: 13,
+            bytes_written: 7,
+            removed_keys: BTreeMap::from([("a".to_string(), 1)]),
+            max_depth: 1,
+            elapsed: stats.elapsed,
+            ..ProcessStats::default()
+        });
+    }
+
+    #[test]
+    fn test_removal_stats() {
+        let input = r#"{"a": 1, "b": {"a": [1, {"x": null}], "c": null}, "items": [{"d": true}, {"d": false}]}"#;
+        let expected_removed_keys = BTreeMap::from([("a".to_string(), 2), ("c".to_string(), 1)]);
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string()])
+                .drop_nulls()
+                .remove_elements_if(Selector::key("d"), b"true".to_vec());
+            let stats = key_remover.process(Cursor::new(input), Vec::new()).unwrap();
+            assert_eq!(stats.removed_keys, expected_removed_keys);
+            assert_eq!(stats.removed_elements, 1);
+            assert_eq!(stats.max_depth, 4);
+        }
+
+        // Keys rejected early are counted in keep mode and bad records are not counted
+        let input = "{\"id\": 1, \"name\": \"n\", \"Name\": 2}\n{\"name\": true, \"id\": [[1]]}\n{\"name\": \n{\"n\": 1}\n";
+        for threads in [1, 3] {
+            let mut key_remover = KeyRemover::init(4, vec!["id".to_string()])
+                .mode(FilterMode::Keep)
+                .ndjson(BadRecordPolicy::Skip)
+                .threads(threads);
+            let stats = key_remover.process(Cursor::new(input), Vec::new()).unwrap();
+            assert_eq!(stats.removed_keys, BTreeMap::from([("Name".to_string(), 1), ("n".to_string(), 1), ("name".to_string(), 2)]));
+            assert_eq!(stats.max_depth, 3);
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let input = r#"{"a": 1, "b": {"a": [1, {"x": null}], "c": null, "long": "0123456789012345678901234567890123456789_"}, "items": [{"d": true}, {"d": false}]}"#;
+        let expected = [
+            r#"{"key":"a","path":"$.a","start":1,"end":7,"preview":"1"}"#,
+            r#"{"key":"a","path":"$.b.a","start":15,"end":36,"preview":"[1, {\"x\": null}]"}"#,
+            r#"{"key":"c","path":"$.b.c","start":38,"end":47,"preview":"null"}"#,
+            r#"{"key":"long","path":"$.b.long","start":49,"end":100,"preview":"\"012345678901234567890123456789012345678..."}"#,
+            r#"{"key":null,"path":"$.items[0]","start":113,"end":124,"preview":"{\"d\": true}"}"#,
+        ];
+        for buffer_size in 1..=input.len() {
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["a".to_string(), "long".to_string()])
+                .drop_nulls()
+                .remove_elements_if(Selector::key("d"), b"true".to_vec())
+                .dry_run(true);
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected.map(|line| format!("{}\n", line)).concat());
+        }
+
+        // Offsets of the buffers released before the deferred end of the removal are kept
+        let input = "{\"a\": [1, 2]    \n  ,  \"b\": {\"x\": 1}, \"items\": [{\"d\": true}   , {\"d\": false}]}";
+        let new_key_remover = |buffer_size| {
+            KeyRemover::init(buffer_size, vec!["a".to_string()])
+                .remove_elements_if(Selector::key("d"), b"true".to_vec())
+                .dry_run(true)
+        };
+        let mut expected = Vec::new();
+        new_key_remover(input.len()).process(Cursor::new(input), &mut expected).unwrap();
+        for buffer_size in 1..input.len() {
+            let mut output = Vec::new();
+            new_key_remover(buffer_size).process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), String::from_utf8(expected.clone()).unwrap());
+        }
+
+        // Offsets are in the stream and paths have the keys rejected early
+        let input = "{\"a\": 1}\n\n{\"b\": {\"a\": 2}}\n[}\n";
+        for threads in [1, 2] {
+            let mut key_remover = KeyRemover::init(4, vec!["a".to_string()])
+                .ndjson(BadRecordPolicy::PassThrough)
+                .threads(threads)
+                .dry_run(true);
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "{\"key\":\"a\",\"path\":\"$.a\",\"start\":1,\"end\":7,\"preview\":\"1\"}\n\
+                {\"key\":\"a\",\"path\":\"$.b.a\",\"start\":17,\"end\":23,\"preview\":\"2\"}\n",
+            );
+        }
+    }
+
+    #[test]
+    fn test_removed_out() {
+        let input = "{\n  \"name\": \"x\",\n  \"email\": \"a@b.c\",\n  \"contact\": {\"phone\": [1, 2],\n    \"email\": null}\n}";
+        let expected = "{\"key\":\"email\",\"path\":\"$.email\",\"value\":\"a@b.c\"}\n\
+            {\"key\":\"phone\",\"path\":\"$.contact.phone\",\"value\":[1,2]}\n\
+            {\"key\":\"email\",\"path\":\"$.contact.email\",\"value\":null}\n";
+        for buffer_size in 1..=input.len() {
+            let removed = SharedBuffer::default();
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["email".to_string(), "phone".to_string()])
+                .removed_out(removed.clone());
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "{\n  \"name\": \"x\",\n  \"contact\": {\n    }\n}");
+            assert_eq!(String::from_utf8(removed.0.lock().unwrap().clone()).unwrap(), expected);
+        }
+
+        // Redacted members are written with their original values
+        let input = "{\"password\": \"p\", \"token\": {\"v\": [1]}, \"email\": \"a@b.c\"}";
+        for buffer_size in 1..=input.len() {
+            let removed = SharedBuffer::default();
+            let mut key_remover = KeyRemover::init(buffer_size, vec!["email".to_string()])
+                .redact(Selector::key("password"), b"null".to_vec())
+                .redact(Selector::key("token"), b"\"***\"".to_vec())
+                .removed_out(removed.clone());
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "{\"password\": null, \"token\": \"***\"}");
+            assert_eq!(
+                String::from_utf8(removed.0.lock().unwrap().clone()).unwrap(),
+                "{\"key\":\"password\",\"path\":\"$.password\",\"value\":\"p\"}\n\
+                {\"key\":\"token\",\"path\":\"$.token\",\"value\":{\"v\":[1]}}\n\
+                {\"key\":\"email\",\"path\":\"$.email\",\"value\":\"a@b.c\"}\n",
+            );
+        }
+
+        // Lines of the records are written in order
+        let input = "{\"a\": 1}\n\n{\"b\": {\"a\": \"0123456789012345678901234567890123456789_\"}}\n[}\n[{\"a\": {}}]\n";
+        for threads in [1, 2] {
+            let removed = SharedBuffer::default();
+            let mut key_remover = KeyRemover::init(4, vec!["a".to_string()])
+                .ndjson(BadRecordPolicy::PassThrough)
+                .threads(threads)
+                .removed_out(removed.clone());
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "{}\n\n{\"b\": {}}\n[}\n[{}]\n");
+            assert_eq!(
+                String::from_utf8(removed.0.lock().unwrap().clone()).unwrap(),
+                "{\"key\":\"a\",\"path\":\"$.a\",\"line\":1,\"value\":1}\n\
+                {\"key\":\"a\",\"path\":\"$.b.a\",\"line\":3,\"value\":\"0123456789012345678901234567890123456789_\"}\n\
+                {\"key\":\"a\",\"path\":\"$[0].a\",\"line\":5,\"value\":{}}\n",
+            );
+        }
+    }
+
+    // Writer of which data can be read after it is moved
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Reader returning the chunks of given sizes and interrupted error between them
+    struct ChunkedReader {
+        data: Vec<u8>,
+        position: usize,
+        chunk_sizes: Vec<usize>,
+        chunk_count: usize,
+        interrupt_next: bool,
+    }
+    impl ChunkedReader {
+        fn new(data: &str, chunk_sizes: Vec<usize>) -> Self {
+            Self {
+                data: data.as_bytes().to_vec(),
+                position: 0,
+                chunk_sizes,
+                chunk_count: 0,
+                interrupt_next: false,
+            }
+        }
+    }
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.interrupt_next {
+                self.interrupt_next = false;
+                return Err(std::io::Error::from(ErrorKind::Interrupted))
+            }
+            self.interrupt_next = true;
+            let chunk_size = self.chunk_sizes[self.chunk_count % self.chunk_sizes.len()];
+            self.chunk_count += 1;
+            let size = chunk_size.min(buf.len()).min(self.data.len() - self.position);
+            buf[..size].copy_from_slice(&self.data[self.position..self.position+size]);
+            self.position += size;
+            Ok(size)
+        }
+    }
+
+    #[test]
+    fn test_short_and_interrupted_reads() {
+        let input = r#"{"id": 1, "note": "a, \"id\": {", "user": {"id": [1, {"id": 2}], "password": "p", "name": "n"}, "tags": ["id", "x"], "id": null}"#;
+        let new_key_remover = |buffer_size| {
+            KeyRemover::init(buffer_size, vec!["id".to_string()])
+                .redact(Selector::key("password"), b"null".to_vec())
+                .rename("name".to_string(), "user_name")
+        };
+        let mut expected = Vec::new();
+        new_key_remover(input.len()).process(Cursor::new(input), &mut expected).unwrap();
+
+        // Pseudo random chunk sizes
+        let mut seed: usize = 42;
+        let random_chunk_sizes: Vec<usize> = (0..97).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % 17 + 1
+        }).collect();
+        for buffer_size in [1, 2, 3, 7, 16, 64, 1024] {
+            for chunk_sizes in [vec![1], vec![2, 1, 5], random_chunk_sizes.clone()] {
+                let mut output = Vec::new();
+                let reader = ChunkedReader::new(input, chunk_sizes);
+                let stats = new_key_remover(buffer_size).process(reader, &mut output).unwrap();
+                assert_eq!(String::from_utf8(output).unwrap(), String::from_utf8(expected.clone()).unwrap());
+                assert_eq!(stats.bytes_read, input.len());
+            }
+        }
+
+        // Short reads held in the unresolved skip do not keep the full buffers
+        let input = format!("{{\"id\": \"{}\", \"name\": \"n\"}}", "x".repeat(2000));
+        let mut key_remover = KeyRemover::init(4096, vec!["id".to_string()]);
+        let mut output = Vec::new();
+        key_remover.process(ChunkedReader::new(&input, vec![1]), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{ \"name\": \"n\"}");
+        assert!(key_remover.peak_held_bytes <= input.len(), "{}", key_remover.peak_held_bytes);
+    }
+
+    // Writer accepting a few bytes of the slices at once and interrupted error between them
+    struct ChunkedWriter {
+        data: Vec<u8>,
+        max_chunk_size: usize,
+        interrupt_next: bool,
+    }
+    impl Write for ChunkedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+            if self.interrupt_next {
+                self.interrupt_next = false;
+                return Err(std::io::Error::from(ErrorKind::Interrupted))
+            }
+            self.interrupt_next = true;
+            let mut written_byte_size = 0;
+            for buf in bufs {
+                let chunk_size = buf.len().min(self.max_chunk_size - written_byte_size);
+                self.data.extend_from_slice(&buf[..chunk_size]);
+                written_byte_size += chunk_size;
+            }
+            Ok(written_byte_size)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_partial_vectored_writes() {
+        let input = r#"{"id": 1, "user": {"id": [1, {"id": 2}], "password": "p", "name": "n"}, "tags": ["id", "x"], "id": null}"#;
+        let new_key_remover = |buffer_size| {
+            KeyRemover::init(buffer_size, vec!["id".to_string()])
+                .redact(Selector::key("password"), b"null".to_vec())
+                .rename("name".to_string(), "user_name")
+        };
+        let mut expected = Vec::new();
+        new_key_remover(input.len()).process(Cursor::new(input), &mut expected).unwrap();
+
+        for buffer_size in [1, 3, 16, 1024] {
+            for max_chunk_size in [1, 2, 5, 64] {
+                let mut writer = ChunkedWriter { data: Vec::new(), max_chunk_size, interrupt_next: false };
+                let stats = new_key_remover(buffer_size).process(Cursor::new(input), &mut writer).unwrap();
+                assert_eq!(String::from_utf8(writer.data).unwrap(), String::from_utf8(expected.clone()).unwrap());
+                assert_eq!(stats.bytes_written, expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_drain_writer_is_identical_to_vectored_writer() {
+        let inputs = [
+            get_sample_json_string(),
+            r#"{"id": 1, "note": "a, \"id\": {\\", "user": {"id": [1, {"id": 2}], "password": "pA", "name": "n"}, "tags": ["id", "x"], "id": null}"#.to_string(),
+            r#"{"password": "p"}"#.to_string(),
+            r#"[{"id": 1}, {"password": [1, 2]}, {"id": {"password": 3}}]"#.to_string(),
+        ];
+        let new_key_remover = |buffer_size, drain_writer| {
+            KeyRemover::init(buffer_size, vec!["id".to_string()])
+                .redact(Selector::key("password"), b"null".to_vec())
+                .rename("name".to_string(), "user_name")
+                .drain_writer(drain_writer)
+        };
+        for input in &inputs {
+            for buffer_size in 1..=input.len() {
+                let results: Vec<(String, usize)> = [false, true].iter().map(|drain_writer| {
+                    let mut output = Vec::new();
+                    let stats = new_key_remover(buffer_size, *drain_writer).process(Cursor::new(input), &mut output).unwrap();
+                    (String::from_utf8(output).unwrap(), stats.bytes_written)
+                }).collect();
+                assert_eq!(results[0], results[1], "{} {}", input, buffer_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_ascii_and_escaped_keys() {
+        let input = r#"{"x": "비밀번호", "비밀번호": 1, "pass\u0077ord": 2, "\ud83d\ude00": 3, "a\"b\/c": 4}"#;
+        let keys = ["비밀번호", "password", "😀", "a\"b/c"];
+        assert_removed_for_all_buffer_sizes(input, &keys, r#"{"x": "비밀번호"}"#);
+
+        for buffer_size in 1..=input.len() {
+            let keys = vec!["비밀번호".to_string(), r#"pass\u0077ord"#.to_string(), "😀".to_string()];
+            let mut key_remover = KeyRemover::init(buffer_size, keys).key_comparison(KeyComparison::RawBytes);
+            let mut output = Vec::new();
+            key_remover.process(Cursor::new(input), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), r#"{"x": "비밀번호", "\ud83d\ude00": 3, "a\"b\/c": 4}"#);
+        }
+    }
+
+    #[test]
+    fn test_search_same_as_scalar() {
+        // Bytes of the characters to be found scattered in the others
+        let alphabet = b"ab \n\"\\{}[],:;|";
+        let mut seed = 1u64;
+        for len in (0..80).chain([1000, 4097]) {
+            for _ in 0..20 {
+                let bytes: Vec<u8> = (0..len).map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    // Sparse to have long runs without the characters
+                    if (seed >> 33).is_multiple_of(16) { alphabet[(seed >> 40) as usize % alphabet.len()] } else { b'x' }
+                }).collect();
+                let structural = bytes.iter().position(|chr| b"\"{}[],".contains(chr));
+                assert_eq!(search::find_structural(&bytes), structural, "{:?}", bytes);
+                assert_eq!(search::scalar::find_structural(&bytes), structural, "{:?}", bytes);
+                assert_eq!(search::find_quote_or_escape(&bytes), search::scalar::find_quote_or_escape(&bytes), "{:?}", bytes);
+                assert_eq!(search::find_newlines(&bytes), search::scalar::find_newlines(&bytes), "{:?}", bytes);
+            }
+        }
+    }
+
+    // Property: output is valid JSON equal to the removal on the parsed document
+    mod removal_properties {
+        use std::io::Cursor;
+
+        use proptest::prelude::*;
+        use serde_json::{Map, Value};
+
+        use super::super::*;
+
+        const KEYS: [&str; 6] = ["a", "b", "c", "d", "a,\"}", ""];
+
+        fn json_value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::Bool),
+                (-1000i64..1000).prop_map(Value::from),
+                (-10.0f64..10.0).prop_map(Value::from),
+                prop::sample::select(vec!["", "x", "a,b", "}]", "\"q\"", "\\", "\u{00e9}\u{1f600}", "\n"]).prop_map(Value::from),
+            ];
+            leaf.prop_recursive(5, 48, 5, |inner| {
+                prop_oneof![
+                    prop::collection::vec(inner.clone(), 0..5).prop_map(Value::Array),
+                    prop::collection::vec((prop::sample::select(KEYS.to_vec()), inner), 0..5).prop_map(|members| {
+                        Value::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect::<Map<_, _>>())
+                    }),
+                ]
+            })
+        }
+
+        // Serialize with the whitespace chosen by the seed around the tokens
+        fn write_with_whitespace(value: &Value, seed: &mut u64, output: &mut String) {
+            match value {
+                Value::Array(elements) => {
+                    output.push('[');
+                    for (idx, element) in elements.iter().enumerate() {
+                        if idx != 0 {
+                            output.push(',');
+                        }
+                        push_whitespace(seed, output);
+                        write_with_whitespace(element, seed, output);
+                        push_whitespace(seed, output);
+                    }
+                    push_whitespace(seed, output);
+                    output.push(']');
+                },
+                Value::Object(members) => {
+                    output.push('{');
+                    for (idx, (key, value)) in members.iter().enumerate() {
+                        // Member is sometimes duplicated with the same value
+                        // (Duplicates with different values are out of scope, the reference keeps only the last one)
+                        let repeats = if next_random(seed).is_multiple_of(8) { 2 } else { 1 };
+                        for repeat in 0..repeats {
+                            if idx != 0 || repeat != 0 {
+                                output.push(',');
+                            }
+                            push_whitespace(seed, output);
+                            output.push_str(&Value::from(key.as_str()).to_string());
+                            push_whitespace(seed, output);
+                            output.push(':');
+                            push_whitespace(seed, output);
+                            write_with_whitespace(value, seed, output);
+                            push_whitespace(seed, output);
+                        }
+                    }
+                    push_whitespace(seed, output);
+                    output.push('}');
+                },
+                scalar => output.push_str(&scalar.to_string()),
+            }
+        }
+
+        fn push_whitespace(seed: &mut u64, output: &mut String) {
+            output.push_str(["", "", " ", "\n  ", "\t", "\r\n"][next_random(seed) as usize % 6]);
+        }
+
+        fn next_random(seed: &mut u64) -> u64 {
+            *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            *seed >> 33
+        }
+
+        fn remove_keys_in_value(value: &mut Value, keys: &[&str]) {
+            match value {
+                Value::Array(elements) => elements.iter_mut().for_each(|element| remove_keys_in_value(element, keys)),
+                Value::Object(members) => {
+                    members.retain(|key, _| !keys.contains(&key.as_str()));
+                    members.values_mut().for_each(|value| remove_keys_in_value(value, keys));
+                },
+                _ => {},
+            }
+        }
+
+        // Whether the value has the selected member after the others are removed
+        //  - Containers without the selected member are kept unless pruned
+        fn keep_keys_in_value(value: &mut Value, keys: &[&str], prune_empty: bool) -> bool {
+            let keeps = |value: &mut Value| value.is_object() | value.is_array() && (keep_keys_in_value(value, keys, prune_empty) | !prune_empty && !keys.is_empty());
+            match value {
+                Value::Array(elements) => {
+                    elements.retain_mut(keeps);
+                    !elements.is_empty()
+                },
+                Value::Object(members) => {
+                    members.retain(|key, value| keys.contains(&key.as_str()) || keeps(value));
+                    !members.is_empty()
+                },
+                _ => false,
+            }
+        }
+
+        // Conditions are checked with the values in input
+        fn remove_by_values_in_value(value: &mut Value, keys: &[&str]) {
+            let is_empty = |value: &Value| match value {
+                Value::Null => true,
+                Value::String(string) => string.is_empty(),
+                Value::Array(elements) => elements.is_empty(),
+                Value::Object(members) => members.is_empty(),
+                _ => false,
+            };
+            match value {
+                Value::Array(elements) => {
+                    elements.retain(|element| element.get("b") != Some(&Value::Bool(true)));
+                    elements.iter_mut().for_each(|element| remove_by_values_in_value(element, keys));
+                },
+                Value::Object(members) => {
+                    members.retain(|key, value| !keys.contains(&key.as_str()) && !is_empty(value));
+                    members.values_mut().for_each(|value| remove_by_values_in_value(value, keys));
+                },
+                _ => {},
+            }
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(512))]
+            #[test]
+            fn removed_output_is_valid_json(
+                value in json_value(),
+                keys in prop::sample::subsequence(KEYS.to_vec(), 0..=KEYS.len()),
+                seed in any::<u64>(),
+                buffer_size in prop_oneof![1usize..8, 8usize..256],
+                layout_aware in any::<bool>(),
+            ) {
+                let mut input = String::new();
+                write_with_whitespace(&value, &mut seed.clone(), &mut input);
+                // Parsed input is the reference (Float is not always same after round trip)
+                let mut expected: Value = serde_json::from_str(&input).unwrap();
+                remove_keys_in_value(&mut expected, &keys);
+
+                let mut key_remover = KeyRemover::init(buffer_size, keys.iter().map(|key| key.to_string()).collect())
+                    .layout_aware(layout_aware);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(&input), &mut output).unwrap();
+                let output = String::from_utf8(output).unwrap();
+                let parsed: Value = serde_json::from_str(&output).map_err(|err| {
+                    TestCaseError::fail(format!("{}\ninput: {}\noutput: {}", err, input, output))
+                })?;
+                prop_assert_eq!(parsed, expected, "input: {}\noutput: {}", input, output);
+            }
+
+            #[test]
+            fn removed_by_values_output_is_valid_json(
+                value in json_value(),
+                keys in prop::sample::subsequence(KEYS.to_vec(), 0..=KEYS.len()),
+                seed in any::<u64>(),
+                buffer_size in prop_oneof![1usize..8, 8usize..256],
+                layout_aware in any::<bool>(),
+            ) {
+                let mut input = String::new();
+                write_with_whitespace(&value, &mut seed.clone(), &mut input);
+                let mut expected: Value = serde_json::from_str(&input).unwrap();
+                remove_by_values_in_value(&mut expected, &keys);
+
+                let mut key_remover = KeyRemover::init(buffer_size, keys.iter().map(|key| key.to_string()).collect())
+                    .drop_nulls()
+                    .drop_empty()
+                    .remove_elements_if(Selector::key("b"), b"true".to_vec())
+                    .layout_aware(layout_aware);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(&input), &mut output).unwrap();
+                let output = String::from_utf8(output).unwrap();
+                let parsed: Value = serde_json::from_str(&output).map_err(|err| {
+                    TestCaseError::fail(format!("{}\ninput: {}\noutput: {}", err, input, output))
+                })?;
+                prop_assert_eq!(parsed, expected, "input: {}\noutput: {}", input, output);
+            }
+
+            #[test]
+            fn kept_output_is_valid_json(
+                value in json_value(),
+                keys in prop::sample::subsequence(KEYS.to_vec(), 0..=KEYS.len()),
+                seed in any::<u64>(),
+                buffer_size in prop_oneof![1usize..8, 8usize..256],
+                layout_aware in any::<bool>(),
+                prune_empty in any::<bool>(),
+            ) {
+                let mut input = String::new();
+                write_with_whitespace(&value, &mut seed.clone(), &mut input);
+                let mut expected: Value = serde_json::from_str(&input).unwrap();
+                keep_keys_in_value(&mut expected, &keys, prune_empty);
+
+                let selectors = keys.iter().map(|key| Selector::key(key)).collect();
+                let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors)
+                    .mode(FilterMode::Keep)
+                    .prune_empty(prune_empty)
+                    .layout_aware(layout_aware);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(&input), &mut output).unwrap();
+                let output = String::from_utf8(output).unwrap();
+                let parsed: Value = serde_json::from_str(&output).map_err(|err| {
+                    TestCaseError::fail(format!("{}\ninput: {}\noutput: {}", err, input, output))
+                })?;
+                prop_assert_eq!(parsed, expected, "input: {}\noutput: {}", input, output);
+            }
+
+            #[test]
+            fn removed_pretty_output_has_no_blank_lines(
+                value in json_value(),
+                keys in prop::sample::subsequence(KEYS.to_vec(), 0..=KEYS.len()),
+                buffer_size in prop_oneof![1usize..8, 8usize..256],
+            ) {
+                let input = serde_json::to_string_pretty(&value).unwrap();
+                let mut expected: Value = serde_json::from_str(&input).unwrap();
+                remove_by_values_in_value(&mut expected, &keys);
+
+                let mut key_remover = KeyRemover::init(buffer_size, keys.iter().map(|key| key.to_string()).collect())
+                    .drop_nulls()
+                    .drop_empty()
+                    .remove_elements_if(Selector::key("b"), b"true".to_vec())
+                    .layout_aware(true);
+                let mut output = Vec::new();
+                key_remover.process(Cursor::new(&input), &mut output).unwrap();
+                let output = String::from_utf8(output).unwrap();
+                let parsed: Value = serde_json::from_str(&output).map_err(|err| {
+                    TestCaseError::fail(format!("{}\ninput: {}\noutput: {}", err, input, output))
+                })?;
+                prop_assert_eq!(parsed, expected, "input: {}\noutput: {}", input, output);
+                prop_assert!(output.lines().all(|line| !line.trim().is_empty()), "input: {}\noutput: {}", input, output);
+                // Layout is same as the pretty output of the removed document (Floats are written from the same value)
+                let mut expected_value = value.clone();
+                remove_by_values_in_value(&mut expected_value, &keys);
+                prop_assert_eq!(&output, &serde_json::to_string_pretty(&expected_value).unwrap(), "input: {}", input);
+            }
+        }
+    }
+
+    // Property: selector is parsed back from its string
+    mod selector_properties {
+        use proptest::prelude::*;
+
+        use super::super::*;
+
+        const KEY_CHARS: [&str; 10] = ["a", "b", ".", "[", "]", "'", "\"", "\\", "^", "$"];
+        const PATH_TOKENS: [&str; 14] = [".a", ".b_c", "..a", ".*", "..*", ".a*", "..?b", "[0]", "[12]", "[*]", "[**]", "..['x.y']", "['it\\'s']", ".^a.*$"];
+
+        fn selector() -> impl Strategy<Value = Selector> {
+            let key = prop::collection::vec(prop::sample::select(KEY_CHARS.to_vec()), 0..6).prop_map(|chars| chars.concat());
+            // Glob starting with "$" is read as path
+            let pattern = prop::collection::vec(prop::sample::select([KEY_CHARS.to_vec(), vec!["*", "?"]].concat()), 1..6)
+                .prop_map(|chars| chars.concat())
+                .prop_filter("Glob of path", |pattern| !pattern.starts_with('$'));
+            prop_oneof![
+                key.clone().prop_map(|key| Selector::key(&key)),
+                key.prop_map(|key| Selector::record_key(&key)),
+                pattern.prop_filter_map("Invalid pattern", |pattern| Selector::key_pattern(&pattern).ok()),
+                prop::collection::vec(prop::sample::select(PATH_TOKENS.to_vec()), 1..6)
+                    .prop_filter_map("Invalid path", |tokens| Selector::parse(&format!("${}", tokens.concat())).ok()),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn selector_is_parsed_from_display(selector in selector()) {
+                let displayed = selector.to_string();
+                prop_assert_eq!(Selector::parse(&displayed), Ok(selector), "{}", displayed);
+            }
+        }
+    }
+}
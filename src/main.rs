@@ -2,16 +2,122 @@ use clap::{AppSettings, Parser};
 use std::path::PathBuf;
 use std::io::{Read, Write, stdin, stdout};
 use std::fs::File;
+use std::num::NonZeroUsize;
 
-use json_key_remover::KeyRemover;
+use json_key_remover::{KeyRemover, Selector, FilterMode, KeyComparison, KeyNormalization, BadRecordPolicy, ProcessStats, OutputFormat, replacement_to_json, quote_json_string, split_outside_brackets, split_once_outside_brackets};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 struct Args {
-    /// Comma separated key list to remove
-    #[clap(short, long, value_parser, value_name = "KEY1,KEY2,...,KEYn")]
-    keys: String,
+    /// Comma separated key list to remove.
+    /// Key can be glob (e.g. _*, *_internal) or regex starting with ^ (e.g. ^x-.*$).
+    #[clap(short, long, value_parser, value_name = "KEY1,KEY2,...,KEYn", required_unless_present_any = &["paths", "keep", "redact", "rename", "drop-nulls", "drop-empty", "remove-if", "remove-elements-if", "validate-only"])]
+    keys: Option<String>,
+
+    /// Comma separated JSONPath list to remove (e.g. $.meta.debug,$.items[*].internal,$..secret).
+    /// Comma in brackets is in the key (e.g. $['a,b']).
+    #[clap(short, long, value_parser, value_name = "PATH1,PATH2,...,PATHn")]
+    paths: Option<String>,
+
+    /// Comma separated key or JSONPath list to keep. Other keys and scalar elements in arrays are removed.
+    /// Key is kept in the root object or in the objects of root array.
+    #[clap(long, value_parser, value_name = "KEY_OR_PATH1,...", conflicts_with_all = &["keys", "paths"])]
+    keep: Option<String>,
+
+    /// With --keep, also remove the objects and arrays left without the kept keys.
+    /// Each object or array that can have them is held in memory until it is closed.
+    #[clap(long, value_parser, requires = "keep")]
+    prune_empty: bool,
+
+    /// Key or JSONPath of which value is replaced with JSON value or text ("***" without REPLACEMENT).
+    /// Key with "=" is written in brackets (e.g. $['a=b']=null).
+    #[clap(long, value_parser, value_name = "KEY[=REPLACEMENT]", multiple_occurrences = true)]
+    redact: Vec<String>,
+
+    /// Comma separated pairs of key and new key to rename.
+    /// Keys are plain names, so a key with "," or "=" cannot be renamed.
+    #[clap(long, value_parser, value_name = "OLD1=NEW1,...,OLDn=NEWn")]
+    rename: Option<String>,
+
+    /// Remove the members of which value is null
+    #[clap(long, value_parser)]
+    drop_nulls: bool,
+
+    /// Remove the members of which value is "", [] or {}
+    #[clap(long, value_parser)]
+    drop_empty: bool,
+
+    /// Key or JSONPath removed when its value is the JSON value or text (e.g. status=deleted, count=0).
+    /// Only scalars, {} and [] can be compared.
+    /// Key with "=" is written in brackets (e.g. $['a=b']=1).
+    #[clap(long, value_parser, value_name = "KEY=VALUE", multiple_occurrences = true)]
+    remove_if: Vec<String>,
+
+    /// Key or JSONPath of member of which object in array is removed when the member has the value (e.g. deleted=true).
+    /// Only scalars, {} and [] can be compared.
+    /// Key with "=" is written in brackets (e.g. $['a=b']=1).
+    #[clap(long, value_parser, value_name = "KEY=VALUE", multiple_occurrences = true)]
+    remove_elements_if: Vec<String>,
+
+    /// Also remove the indentation and newline of removed members so no blank line is left in pretty-printed JSON.
+    /// Containers left without members are written as {} or [].
+    #[clap(long, value_parser)]
+    layout_aware: bool,
+
+    /// Remove whitespace outside of strings in output
+    #[clap(long, value_parser)]
+    minify: bool,
+
+    /// Pretty-print output with N (1 or more) spaces per depth
+    #[clap(long, value_parser, value_name = "N", conflicts_with_all = &["minify", "ndjson"])]
+    indent: Option<NonZeroUsize>,
+
+    /// Compare keys with the raw bytes in JSON without decoding escapes (e.g. \u0061)
+    #[clap(long, value_parser)]
+    raw_keys: bool,
+
+    /// Compare keys ignoring case (e.g. Password, PASSWORD and password)
+    #[clap(long, value_parser)]
+    ignore_case: bool,
+
+    /// Compare keys ignoring case, '-' and '_', and camelCase and snake_case (e.g. userId, user-id and USER_ID)
+    #[clap(long, value_parser)]
+    normalize_keys: bool,
+
+    /// Validate full JSON grammar and stop at the first error
+    #[clap(long, value_parser)]
+    strict: bool,
+
+    /// Only validate the input in strict mode without writing output
+    #[clap(long, value_parser, conflicts_with = "output")]
+    validate_only: bool,
+
+    /// Process each line as a JSON document (NDJSON, JSON Lines)
+    #[clap(long, value_parser)]
+    ndjson: bool,
+
+    /// Way to deal with malformed line in NDJSON mode (fail, skip or pass-through).
+    /// Each line is validated with full JSON grammar.
+    #[clap(long, value_parser, value_name = "POLICY", default_value = "skip", requires = "ndjson")]
+    bad_records: BadRecordPolicy,
+
+    /// Worker threads to process lines in NDJSON mode
+    #[clap(long, value_parser, value_name = "N", default_value_t = 1, requires = "ndjson")]
+    threads: usize,
+
+    /// Write the removals and redactions (key, path, byte offsets and value preview) as JSON Lines instead of the output
+    #[clap(long, value_parser, conflicts_with_all = &["minify", "indent"])]
+    dry_run: bool,
+
+    /// Write the removed members (key, path, line in NDJSON mode and value) as JSON Lines to the file.
+    /// Redacted members are written with their original values.
+    #[clap(long, value_parser, value_name = "FILE", conflicts_with = "validate-only")]
+    removed_out: Option<PathBuf>,
+
+    /// Print the statistics of removals to STDERR (human or json)
+    #[clap(long, value_parser = ["human", "json"], value_name = "FORMAT", min_values = 0, default_missing_value = "human")]
+    stats: Option<String>,
 
     /// Input file path [default: STDIN]
     #[clap(short, long, value_parser, value_name = "FILE")]
@@ -32,29 +138,216 @@ fn main() {
     // (1) Init
     //  (1) Get input and output stream
     let reader: Box<dyn Read> = if let Some(path_buf) = &args.input {
-        Box::new(File::open(path_buf).unwrap())
+        Box::new(File::open(path_buf).unwrap_or_else(|err| {
+            eprintln!("error: Failed to open {}: {}", path_buf.display(), err);
+            std::process::exit(1);
+        }))
     } else {
         let stdin = stdin();
         Box::new(stdin)
     };
+    if args.validate_only {
+        let mut key_remover = KeyRemover::init_with_selectors(args.size as usize, Vec::new());
+        if args.ndjson {
+            key_remover = key_remover.ndjson(args.bad_records).threads(args.threads);
+        }
+        match key_remover.validate(reader) {
+            Ok(stats) => {
+                report_bad_records(&stats);
+                report_stats(&stats, args.stats.as_deref());
+                if !stats.bad_records.is_empty() {
+                    std::process::exit(1);
+                }
+            },
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            },
+        }
+        return
+    }
     let writer: Box<dyn Write> = if let Some(path_buf) = &args.output {
-        Box::new(File::create(path_buf).unwrap())
+        Box::new(File::create(path_buf).unwrap_or_else(|err| {
+            eprintln!("error: Failed to create {}: {}", path_buf.display(), err);
+            std::process::exit(1);
+        }))
     } else {
         let stdout = stdout();
         Box::new(stdout)
     };
-    //  (2) Get vector of keys to remove
-    let keys_to_remove: Vec<String> = args.keys.split(',').map(|x| x.to_string()).collect();
-    eprintln!("To remove");
-    for (idx, key) in keys_to_remove.iter().enumerate() {
-        eprintln!(" {}: {}", idx+1, key);
-    }
+    //  (2) Get vector of selectors
+    let (mode, selectors) = match &args.keep {
+        Some(keep) => {
+            let selectors: Result<Vec<Selector>, String> = split_outside_brackets(keep, ',').into_iter().map(|key_or_path| {
+                if key_or_path.starts_with('$') {
+                    Selector::parse(key_or_path)
+                } else {
+                    Ok(Selector::record_key(key_or_path))
+                }
+            }).collect();
+            let selectors = selectors.unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            });
+            (FilterMode::Keep, selectors)
+        },
+        None => {
+            let mut selectors: Vec<Selector> = match &args.keys {
+                Some(keys) => {
+                    let selectors: Result<Vec<Selector>, String> = keys.split(',').map(Selector::key_pattern).collect();
+                    selectors.unwrap_or_else(|err| {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    })
+                },
+                None => Vec::new(),
+            };
+            if let Some(paths) = &args.paths {
+                // Comma in the brackets is in the key (e.g. $['a,b'])
+                let paths: Result<Vec<Selector>, String> = split_outside_brackets(paths, ',').into_iter().map(Selector::parse).collect();
+                selectors.extend(paths.unwrap_or_else(|err| {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }));
+            }
+            (FilterMode::Remove, selectors)
+        },
+    };
     //  (3) Get buffer size
     let buffer_size = args.size as usize;
 
     // (2) Init key remover
-    let mut key_remover = KeyRemover::init(buffer_size, keys_to_remove);
+    let mut key_remover = KeyRemover::init_with_selectors(buffer_size, selectors)
+        .mode(mode)
+        .prune_empty(args.prune_empty)
+        .strict(args.strict)
+        .layout_aware(args.layout_aware)
+        .dry_run(args.dry_run);
+    if args.raw_keys {
+        key_remover = key_remover.key_comparison(KeyComparison::RawBytes);
+    }
+    if args.ignore_case || args.normalize_keys {
+        key_remover = key_remover.key_normalization(KeyNormalization {
+            ignore_case: true,
+            unify_separators: args.normalize_keys,
+            split_camel_case: args.normalize_keys,
+        });
+    }
+    if args.ndjson {
+        key_remover = key_remover.ndjson(args.bad_records).threads(args.threads);
+    }
+    if let Some(path_buf) = &args.removed_out {
+        let removed_writer = File::create(path_buf).unwrap_or_else(|err| {
+            eprintln!("error: Failed to create {}: {}", path_buf.display(), err);
+            std::process::exit(1);
+        });
+        key_remover = key_remover.removed_out(removed_writer);
+    }
+    if args.minify {
+        key_remover = key_remover.output_format(OutputFormat::Minify);
+    } else if let Some(indent) = args.indent {
+        key_remover = key_remover.output_format(OutputFormat::Indent(indent));
+    }
+    for redaction in &args.redact {
+        let (key_or_path, replacement) = match split_once_outside_brackets(redaction, '=') {
+            Some((key_or_path, replacement)) => (key_or_path, replacement_to_json(replacement)),
+            None => (redaction.as_str(), b"\"***\"".to_vec()),
+        };
+        let selector = Selector::parse(key_or_path).unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        });
+        key_remover = key_remover.redact(selector, replacement);
+    }
+    if args.drop_nulls {
+        key_remover = key_remover.drop_nulls();
+    }
+    if args.drop_empty {
+        key_remover = key_remover.drop_empty();
+    }
+    for condition in &args.remove_if {
+        let (selector, value) = parse_condition(condition);
+        key_remover = key_remover.remove_if(selector, value);
+    }
+    for condition in &args.remove_elements_if {
+        let (selector, value) = parse_condition(condition);
+        key_remover = key_remover.remove_elements_if(selector, value);
+    }
+    if let Some(rename) = &args.rename {
+        for pair in rename.split(',') {
+            let (key, new_key) = pair.split_once('=').unwrap_or_else(|| {
+                eprintln!("error: Rename is not in the form of OLD=NEW: {}", pair);
+                std::process::exit(2);
+            });
+            key_remover = key_remover.rename(key.to_string(), new_key);
+        }
+    }
 
     // (3) Run
-    key_remover.process(reader, writer);
+    match key_remover.process(reader, writer) {
+        Ok(stats) => {
+            report_bad_records(&stats);
+            report_stats(&stats, args.stats.as_deref());
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn report_bad_records(stats: &ProcessStats) {
+    for bad_record in &stats.bad_records {
+        eprintln!("warning: {}", bad_record.message);
+    }
+}
+
+fn report_stats(stats: &ProcessStats, format: Option<&str>) {
+    match format {
+        Some("json") => {
+            let removed_keys: Vec<String> = stats.removed_keys.iter().map(|(key, count)| {
+                format!("{}:{}", quote_json_string(key), count)
+            }).collect();
+            eprintln!(
+                "{{\"bytes_read\":{},\"bytes_written\":{},\"removed_keys\":{{{}}},\"removed_elements\":{},\"max_depth\":{},\"elapsed_seconds\":{},\"records\":{},\"bad_records\":{}}}",
+                stats.bytes_read, stats.bytes_written, removed_keys.join(","), stats.removed_elements,
+                stats.max_depth, stats.elapsed.as_secs_f64(), stats.records, stats.bad_records.len(),
+            );
+        },
+        Some(_) => {
+            eprintln!("Bytes read: {}", stats.bytes_read);
+            eprintln!("Bytes written: {}", stats.bytes_written);
+            eprintln!("Removed keys: {}", stats.removed_keys.values().sum::<usize>());
+            for (key, count) in &stats.removed_keys {
+                eprintln!(" {}: {}", key, count);
+            }
+            eprintln!("Removed elements: {}", stats.removed_elements);
+            eprintln!("Max depth: {}", stats.max_depth);
+            eprintln!("Elapsed: {:.3}s", stats.elapsed.as_secs_f64());
+            if stats.records != 0 {
+                eprintln!("Records: {} ({} bad)", stats.records, stats.bad_records.len());
+            }
+        },
+        None => {},
+    }
+}
+
+// Parse "KEY=VALUE" with the key or JSONPath
+fn parse_condition(condition: &str) -> (Selector, Vec<u8>) {
+    // "=" in the brackets is in the key (e.g. $['a=b']=1)
+    let (key_or_path, value) = split_once_outside_brackets(condition, '=').unwrap_or_else(|| {
+        eprintln!("error: Condition is not in the form of KEY=VALUE: {}", condition);
+        std::process::exit(2);
+    });
+    let selector = Selector::parse(key_or_path).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    });
+    let value = replacement_to_json(value);
+    let trimmed = value.trim_ascii();
+    if matches!(trimmed.first(), Some(b'{' | b'[')) && !trimmed[1..trimmed.len()-1].trim_ascii().is_empty() {
+        eprintln!("error: Only scalars, {{}} and [] can be compared, not the value of {}", condition);
+        std::process::exit(2);
+    }
+    (selector, value)
 }
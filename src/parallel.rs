@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write, ErrorKind, Cursor};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::{KeyRemover, KeyRemoverError, ProcessStats, BadRecordPolicy, RecordPosition, RemovedOut};
+use crate::scanner::ScanStats;
+
+// Lines sent to the worker
+struct Chunk {
+    sequence: usize,
+    position: RecordPosition,
+    data: Vec<u8>,
+}
+
+// Processed chunk
+struct ChunkOutput {
+    sequence: usize,
+    output: Vec<u8>, // Written before the error
+    removed_lines: Vec<u8>, // Written to the side output
+    result: Result<ProcessStats, KeyRemoverError>,
+}
+
+impl KeyRemover {
+    // Split the input into the chunks of lines and process them with worker threads
+    //  - Chunks in flight are limited to bound the memory
+    pub(crate) fn process_ndjson_in_parallel<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        bad_record_policy: BadRecordPolicy,
+    ) -> Result<ProcessStats, KeyRemoverError> where
+        R: Read, W: Write,
+    {
+        let max_chunks_in_flight = self.threads * 2;
+        let (chunk_sender, chunk_receiver) = mpsc::channel::<Chunk>();
+        let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+        let (output_sender, output_receiver) = mpsc::channel::<ChunkOutput>();
+
+        thread::scope(|scope| {
+            // (1) Spawn workers
+            for _ in 0..self.threads {
+                let mut worker = KeyRemover::init_with_scanner(self.buffer_size, self.scanner.fresh());
+                worker.formatter = self.formatter.clone();
+                worker.dry_run = self.dry_run;
+                worker.removed_lines = self.removed_lines.as_ref().map(|_| Vec::new());
+                let chunk_receiver = Arc::clone(&chunk_receiver);
+                let output_sender = output_sender.clone();
+                scope.spawn(move || {
+                    // Lock is released right after receiving
+                    while let Ok(chunk) = chunk_receiver.lock().unwrap().recv() {
+                        let mut output = Vec::with_capacity(chunk.data.len());
+                        worker.stats = ProcessStats::default();
+                        let result = worker.process_ndjson(
+                            Cursor::new(&chunk.data), &mut output, bad_record_policy, chunk.position,
+                        );
+                        let removed_lines = worker.removed_lines.as_mut().map(std::mem::take).unwrap_or_default();
+                        let chunk_output = ChunkOutput { sequence: chunk.sequence, output, removed_lines, result };
+                        if output_sender.send(chunk_output).is_err() {
+                            break
+                        }
+                    }
+                });
+            }
+            drop(output_sender);
+
+            // (2) Send chunks and write outputs in order
+            //  - Workers stop when the sender is dropped on return
+            let mut reorderer = Reorderer::default();
+            let mut buffer = vec![0; self.buffer_size];
+            let mut pending = Vec::new(); // Data after the last newline
+            let mut position = RecordPosition::default();
+            let mut sequence = 0;
+            let mut stats = ProcessStats::default();
+            let mut end_of_input = false;
+            while !end_of_input {
+                // (1) Fill chunk until it has a newline and reaches the buffer size
+                let filled_byte_size = match reader.read(&mut buffer) {
+                    Ok(filled_byte_size) => filled_byte_size,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                stats.bytes_read += filled_byte_size;
+                pending.extend_from_slice(&buffer[..filled_byte_size]);
+                end_of_input = filled_byte_size == 0;
+                let chunk_length = if end_of_input {
+                    pending.len()
+                } else if pending.len() < self.buffer_size {
+                    continue
+                } else {
+                    match pending.iter().rposition(|chr| *chr == b'\n') {
+                        Some(newline_position) => newline_position + 1,
+                        None => continue, // Line is longer than buffer
+                    }
+                };
+                if chunk_length == 0 {
+                    break
+                }
+
+                // (2) Send chunk
+                let remained = pending.split_off(chunk_length);
+                let data = std::mem::replace(&mut pending, remained);
+                let next_position = RecordPosition {
+                    line: position.line + data.iter().filter(|chr| **chr == b'\n').count(),
+                    offset: position.offset + data.len(),
+                };
+                chunk_sender.send(Chunk { sequence, position, data })
+                    .map_err(|_| KeyRemoverError::Internal("Workers are stopped".to_string()))?;
+                sequence += 1;
+                position = next_position;
+
+                // (3) Write outputs until the chunks in flight are under the limit
+                while sequence - reorderer.next_sequence >= max_chunks_in_flight {
+                    reorderer.write_next(&output_receiver, &mut writer, &mut self.removed_out, &mut stats)?;
+                }
+            }
+            while reorderer.next_sequence < sequence {
+                reorderer.write_next(&output_receiver, &mut writer, &mut self.removed_out, &mut stats)?;
+            }
+            drop(chunk_sender);
+
+            writer.flush()?;
+            Ok(stats)
+        })
+    }
+}
+
+// Outputs of chunks arriving out of order
+#[derive(Default)]
+struct Reorderer {
+    next_sequence: usize,
+    arrived: BTreeMap<usize, ChunkOutput>,
+}
+
+impl Reorderer {
+    // Write the output of the next chunk waiting for it if needed
+    fn write_next<W: Write>(
+        &mut self,
+        output_receiver: &mpsc::Receiver<ChunkOutput>,
+        writer: &mut W,
+        removed_out: &mut Option<RemovedOut>,
+        stats: &mut ProcessStats,
+    ) -> Result<(), KeyRemoverError> {
+        let chunk_output = loop {
+            if let Some(chunk_output) = self.arrived.remove(&self.next_sequence) {
+                break chunk_output
+            }
+            let chunk_output = output_receiver.recv()
+                .map_err(|_| KeyRemoverError::Internal("Workers are stopped".to_string()))?;
+            self.arrived.insert(chunk_output.sequence, chunk_output);
+        };
+        self.next_sequence += 1;
+
+        writer.write_all(&chunk_output.output)?;
+        if let Some(removed_out) = removed_out.as_mut() {
+            removed_out.0.write_all(&chunk_output.removed_lines)?;
+        }
+        stats.bytes_written += chunk_output.output.len();
+        let chunk_stats = chunk_output.result?;
+        stats.records += chunk_stats.records;
+        stats.bad_records.extend(chunk_stats.bad_records);
+        stats.add_scan_stats(ScanStats {
+            removed_keys: chunk_stats.removed_keys,
+            removed_elements: chunk_stats.removed_elements,
+            max_depth: chunk_stats.max_depth,
+        });
+        Ok(())
+    }
+}
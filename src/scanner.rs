@@ -1,3 +1,14 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::selector::{Filter, KeyAction, PathSegment, ConditionTarget};
+use crate::error::KeyRemoverError;
+use crate::validator::Validator;
+use crate::layout::LayoutCleaner;
+use crate::dry_run::{MatchRecorder, MatchCandidate};
+use crate::search;
+use crate::trie::KeyTrie;
+
 const OCB_CHR: u8 = b'{'; // Opening Curly Bracket
 const CCB_CHR: u8 = b'}'; // Closing Curly Bracket
 const OSB_CHR: u8 = b'['; // Opening Square Bracket
@@ -15,15 +26,32 @@ const ESCAPE_CHR: u8 = b'\\'; // Escape
 
 #[derive(Debug)]
 pub struct Scanner {
-    pub keys_to_remove: Vec<String>,
+    pub filter: Filter,
+    pub key_comparison: KeyComparison,
     pub next_buffer_index: usize,
+    pub location: Location, // Location of next character
+    pub validator: Option<Validator>, // Validate full grammar in strict mode
+    pub layout_cleaner: Option<LayoutCleaner>, // Remove the lines left blank by the removals
+    pub match_recorder: Option<MatchRecorder>, // List the removals in dry-run mode
+    pub fast_path: bool, // Jump over the characters not changing the state
+    pub prune_empty: bool, // Hold the containers in keep mode to remove them if the selected member is not found
+    pub stats: ScanStats,
     // State
     pub state: ScannerState,
     // Checker
+    structure_tracker: StructureTracker,
     waiting_condition: WaitingCondition,
     key_cache: KeyCache,
+    key_action: KeyAction,
     value_type_definer: ValueTypeDefiner,
     value_range_checker: ValueRangeChecker,
+    pending_removal: Option<PendingRemoval>, // Removal waiting for the value
+    element_opener: WaitingCondition, // Last '[' or ',' in array
+    awaiting_element: bool, // Comma is held until the next element
+    pending_elements: Vec<PendingElement>, // Objects in array held to check their members (Outermost first)
+    element_expected: bool, // Next value is the element removed unless it has the selected member in keep mode
+    removing_element: Option<bool>, // Whether the opener of the element being removed is comma
+    pending_ancestors: Vec<PendingAncestor>, // Containers held in keep mode until the selected member is found (Outermost first)
     pub skip_end_msg_cache: Message,
     // Message Queue
     pub queue: Vec<Message>,
@@ -32,7 +60,7 @@ pub struct Scanner {
 #[derive(Debug)]
 pub enum ScannerState {
     WaitingNextKey,
-    MeetKeyCandOpener, // Meet '{' or '[' or ','
+    MeetKeyCandOpener, // Meet '{' or ',' in object
     ConfirmingKey, // Meet " next to CandSite
     DefiningValueType,
     CheckingValueRange,
@@ -46,6 +74,7 @@ pub enum Message {
     SkipStartFrom(ChrIndex),
     SkipEndTo(ChrIndex),
     SkipEndPreviousTo(ChrIndex),
+    ReplaceFrom(ChrIndex, Vec<u8>), // Skip start with the bytes to write instead
 }
 
 impl Default for Message {
@@ -53,171 +82,1046 @@ impl Default for Message {
         Self::SkipStartFrom((0,0))
     }
 }
+impl Message {
+    // Exclusive boundary of the range (skip start is inclusive and skip end is exclusive)
+    pub fn boundary(&self) -> ChrIndex {
+        match self {
+            Self::SkipStartFrom(chr_index) => *chr_index,
+            Self::SkipEndTo(chr_index) => (chr_index.0, chr_index.1 + 1),
+            Self::SkipEndPreviousTo(chr_index) => *chr_index,
+            Self::ReplaceFrom(chr_index, _) => *chr_index,
+        }
+    }
+}
 
 impl Scanner {
-    pub fn new(keys_to_remove: Vec<String>) -> Self {
+    pub fn new(filter: Filter) -> Self {
         Self {
-            keys_to_remove,
+            filter,
+            key_comparison: KeyComparison::Decoded,
             next_buffer_index: 0,
+            location: Location::default(),
+            validator: None,
+            layout_cleaner: None,
+            match_recorder: None,
+            fast_path: true,
+            prune_empty: false,
+            stats: ScanStats::default(),
             state: ScannerState::WaitingNextKey,
+            structure_tracker: StructureTracker::default(),
             waiting_condition: WaitingCondition::default(),
             key_cache: KeyCache::default(),
+            key_action: KeyAction::Keep,
             value_type_definer: ValueTypeDefiner::default(),
             value_range_checker: ValueRangeChecker::default(),
+            pending_removal: None,
+            element_opener: WaitingCondition::default(),
+            awaiting_element: false,
+            pending_elements: Vec::new(),
+            element_expected: false,
+            removing_element: None,
+            pending_ancestors: Vec::new(),
             skip_end_msg_cache: Message::default(),
             queue: Vec::new(),
         }
     }
-    pub fn process_new_buffer(&mut self, buffer: &[u8]) {
+    // New scanner with the same configuration
+    pub fn fresh(&self) -> Self {
+        let mut scanner = Self::new(self.filter.clone());
+        scanner.key_comparison = self.key_comparison;
+        scanner.fast_path = self.fast_path;
+        scanner.prune_empty = self.prune_empty;
+        if self.validator.is_some() {
+            scanner.validator = Some(Validator::default());
+        }
+        if self.layout_cleaner.is_some() {
+            scanner.layout_cleaner = Some(LayoutCleaner::default());
+        }
+        scanner.match_recorder = self.match_recorder.as_ref().map(MatchRecorder::fresh);
+        scanner
+    }
+    // Start the new document with the same configuration
+    pub fn reset(&mut self) {
+        self.next_buffer_index = 0;
+        self.location = Location::default();
+        if self.validator.is_some() {
+            self.validator = Some(Validator::default());
+        }
+        if self.layout_cleaner.is_some() {
+            self.layout_cleaner = Some(LayoutCleaner::default());
+        }
+        self.match_recorder = self.match_recorder.as_ref().map(MatchRecorder::fresh);
+        self.state = ScannerState::WaitingNextKey;
+        self.structure_tracker = StructureTracker::default();
+        self.waiting_condition = WaitingCondition::default();
+        self.key_cache = KeyCache::default();
+        self.key_action = KeyAction::Keep;
+        self.value_type_definer = ValueTypeDefiner::default();
+        self.value_range_checker = ValueRangeChecker::default();
+        self.pending_removal = None;
+        self.element_opener = WaitingCondition::default();
+        self.awaiting_element = false;
+        self.pending_elements.clear();
+        self.element_expected = false;
+        self.removing_element = None;
+        self.pending_ancestors.clear();
+        self.skip_end_msg_cache = Message::default();
+        self.queue.clear();
+        self.stats = ScanStats::default();
+    }
+    pub fn process_new_buffer(&mut self, buffer: &[u8]) -> Result<(), KeyRemoverError> {
+        // Positions before the resolved data are not recorded anymore
+        let resolved_buffer_index = self.resolved_until_by_state().map_or(self.next_buffer_index, |resolved_until| resolved_until.0);
+        if let Some(match_recorder) = self.match_recorder.as_mut() {
+            match_recorder.release_buffers(resolved_buffer_index);
+            match_recorder.start_buffer(self.location.offset);
+        }
         // (1) Deal with each character
-        buffer.iter().enumerate().for_each(|(pos, chr)| {
-            match &self.state {
-                ScannerState::WaitingNextKey => {
-                    let meet_key_opener = self.waiting_condition.check_key_opener(chr);
-                    if meet_key_opener {
-                        self.waiting_condition.update_position((self.next_buffer_index, pos));
-                        self.state = ScannerState::MeetKeyCandOpener;
+        let mut pos = 0;
+        while pos < buffer.len() {
+            if self.fast_path {
+                let skippable_length = self.skippable_length(&buffer[pos..]);
+                if skippable_length != 0 {
+                    self.skip(&buffer[pos..pos+skippable_length], pos)?;
+                    pos += skippable_length;
+                    continue
+                }
+            }
+            let chr = &buffer[pos];
+
+            if let Some(Err(message)) = self.validator.as_mut().map(|validator| validator.validate(*chr)) {
+                return Err(self.malformed(message))
+            }
+            // Characters inside of strings are never structural
+            let lexeme = self.structure_tracker.track(*chr).map_err(|message| self.malformed(message))?;
+            if let Lexeme::Structural(OCB_CHR | OSB_CHR) = lexeme {
+                self.stats.max_depth = self.stats.max_depth.max(self.structure_tracker.containers.len());
+            }
+            // Value is fed from the next character of the match start
+            if self.match_recorder.is_some() {
+                self.feed_match_candidates(&buffer[pos..pos+1]);
+            }
+
+            'state: {
+                // Member with the container is removed only when the container is empty
+                if let Some(PendingRemoval { container_closer: Some(closer), .. }) = self.pending_removal {
+                    if lexeme == Lexeme::Structural(closer) {
+                        self.remove_pending_value();
+                        self.value_range_checker = ValueRangeChecker::new(ValueType::Object);
+                        self.close_value(false, lexeme, pos);
+                        break 'state
                     }
-                },
-                ScannerState::MeetKeyCandOpener => {
-                    match *chr {
-                        SPACE_CHR | TAP_CHR | NEWLINE_CHR | RETURN_CHR => {
-                            // pass
-                        },
-                        DQ_CHR => {
-                            let new_key_cache = KeyCache::new((self.next_buffer_index, pos));
-                            self.key_cache = new_key_cache;
-                            self.state = ScannerState::ConfirmingKey;
-                        },
-                        _ => {
-                            // Return to wait key
-                            // In the case of
-                            //  - {}
-                            //  - []
-                            //  - ...
-                            self.state = ScannerState::WaitingNextKey;
-                        },
+                    if !matches!(lexeme, Lexeme::Whitespace) {
+                        self.keep_pending_value();
                     }
-                },
-                ScannerState::ConfirmingKey => {
-                    let confirmed = self.key_cache.confirm_key(chr);
-                    if confirmed {
-                        // (1) Check if key is to remove
-                        let mut need_to_remove = false;
-                        for string in &self.keys_to_remove {
-                            if *string == self.key_cache.key_string {
-                                need_to_remove = true;
-                                break
+                }
+                // Element in array is removed unless it has the selected member in keep mode
+                //  - Skip start is taken before the opener is changed by the element
+                let starts_element = self.element_expected && !matches!(lexeme, Lexeme::Whitespace | Lexeme::Structural(CSB_CHR));
+                let element_skip_start = if starts_element { Some(self.element_skip_start(pos)) } else { None };
+                self.element_expected = match lexeme {
+                    Lexeme::Structural(OSB_CHR) => self.filter.removes_unselected_element(&self.structure_tracker.path),
+                    Lexeme::Structural(COMMA_CHR) if !self.structure_tracker.in_object() => {
+                        self.filter.removes_unselected_element(&self.structure_tracker.path)
+                    },
+                    Lexeme::Whitespace => self.element_expected,
+                    _ => false,
+                };
+                // Object in array can be removed by its member
+                self.awaiting_element = match lexeme {
+                    Lexeme::Structural(OSB_CHR) => {
+                        self.element_opener.key_cand_opener = KeyCandOpener::MeetNonComma;
+                        self.element_opener.update_position((self.next_buffer_index, pos), None);
+                        false
+                    },
+                    Lexeme::Structural(COMMA_CHR) if !self.structure_tracker.in_object() => {
+                        self.element_opener.key_cand_opener = KeyCandOpener::MeetComma;
+                        self.element_opener.update_position((self.next_buffer_index, pos), self.blank_line_start());
+                        self.element_expected || self.filter.may_remove_element(&self.structure_tracker.path)
+                    },
+                    Lexeme::Structural(OCB_CHR) if matches!(self.state, ScannerState::WaitingNextKey) => {
+                        self.start_element(pos);
+                        false
+                    },
+                    Lexeme::Whitespace => self.awaiting_element,
+                    _ => false,
+                };
+                self.read_member_value(*chr);
+
+                match &self.state {
+                    ScannerState::WaitingNextKey => {
+                        if let Some(element_skip_start) = element_skip_start {
+                            self.start_unselected_element(element_skip_start, lexeme, pos, *chr);
+                        }
+                        // Removed element is not read for the keys
+                        if let ScannerState::WaitingNextKey = self.state {
+                            self.wait_key_opener(lexeme, pos);
+                        }
+                    },
+                    ScannerState::MeetKeyCandOpener => {
+                        match lexeme {
+                            Lexeme::Whitespace => {
+                                // pass
+                            },
+                            Lexeme::StringOpen => {
+                                // Keys are not rejected early to list the full paths in dry-run mode
+                                let check_prefix = self.filter.key_trie().is_some() && self.match_recorder.is_none();
+                                // Name of the rejected key is needed only to count its removal
+                                let keep_rejected = self.filter.may_remove_rejected_key();
+                                let mut new_key_cache = KeyCache::new((self.next_buffer_index, pos), check_prefix, keep_rejected);
+                                new_key_cache.dq_line_start = self.blank_line_start();
+                                self.key_cache = new_key_cache;
+                                self.state = ScannerState::ConfirmingKey;
+                            },
+                            _ => {
+                                // Return to wait key
+                                // In the case of
+                                //  - {}
+                                //  - ...
+                                self.state = ScannerState::WaitingNextKey;
+                                self.wait_key_opener(lexeme, pos);
+                            },
+                        }
+                    },
+                    ScannerState::ConfirmingKey => {
+                        let confirmed = self.key_cache.confirm_key(chr, self.filter.key_trie(), self.key_comparison);
+                        if confirmed {
+                            self.key_cache.dq_end_position = (self.next_buffer_index, pos);
+                            // (1) Check if key is to remove
+                            let key_string = self.filter.normalize_key(self.key_cache.key_string(self.key_comparison));
+                            self.structure_tracker.update_key(key_string);
+                            self.key_action = self.filter.key_action(&self.structure_tracker.path);
+                            if !matches!(self.key_action, KeyAction::Remove | KeyAction::RemoveIfNotContainer) {
+                                // Containers having the selected member are kept in keep mode
+                                self.pending_ancestors.clear();
                             }
-                        };
-                        // (2) Change state
-                        if need_to_remove {
-                            let skip_start_msg = if self.waiting_condition.key_cand_opener_is_comma() {
-                                Message::SkipStartFrom(self.waiting_condition.opener_position)
+                            self.start_reading_member_value();
+                            // (2) Change state
+                            //  - Key is renamed after the value is checked if it can be removed by the value
+                            if let KeyAction::Keep | KeyAction::Redact(_) = self.key_action {
+                                self.rename_key();
+                            }
+                            if let KeyAction::Keep = self.key_action {
+                                // Return to wait key
+                                self.state = ScannerState::WaitingNextKey;
                             } else {
-                                Message::SkipStartFrom(self.key_cache.dq_start_position)
-                            };
-                            self.queue.push(skip_start_msg);
-                            self.value_type_definer.init();
-                            self.state = ScannerState::DefiningValueType;
-                        } else {
-                            // Return to wait key
-                            self.state = ScannerState::WaitingNextKey;
+                                self.value_type_definer.init();
+                                self.state = ScannerState::DefiningValueType;
+                            }
                         }
-                    }
-                },
-                ScannerState::DefiningValueType => {
-                    let defined_value_type = self.value_type_definer.define_value_type(chr);
-
-                    if let Some(value_type) = defined_value_type {
-                        self.value_range_checker = ValueRangeChecker::new(value_type);
-                        self.state = ScannerState::CheckingValueRange;
-                    }
-                },
-                ScannerState::CheckingValueRange => {
-                    let closing_condition = self.value_range_checker.check_meeting_closing(*chr);
-
-                    if let ClosingCondition::ClosedWithComma(closed_with_comma) = closing_condition {
-                        // (1) Get value end position
-                        let value_is_to_previous = self.value_range_checker.range_is_to_previous_chr();
-                        let skip_end_msg = if value_is_to_previous {
-                            Message::SkipEndPreviousTo((self.next_buffer_index, pos))
-                        } else {
-                            Message::SkipEndTo((self.next_buffer_index, pos))
-                        };
-                        
-                        // (2) Next state
-                        let key_opener_is_comma = self.waiting_condition.key_cand_opener_is_comma();
-                        if key_opener_is_comma {
-                            if closed_with_comma {
-                                self.queue.push(skip_end_msg);
-
-                                self.waiting_condition.key_cand_opener = KeyCandOpener::MeetComma;
-                                self.waiting_condition.update_position((self.next_buffer_index, pos));
-                                self.state = ScannerState::MeetKeyCandOpener;
-                            } else {
-                                self.queue.push(skip_end_msg);
+                    },
+                    ScannerState::DefiningValueType => {
+                        let defined_value_type = self.value_type_definer.define_value_type(chr).map_err(|message| self.malformed(message))?;
 
+                        if let Some(value_type) = defined_value_type {
+                            // Path of the member (Container is already pushed)
+                            let member_depth = self.structure_tracker.path.len() - usize::from(value_type.is_container());
+                            let removes_empty_container = value_type.is_container() && self.key_action == KeyAction::RemoveIfValue
+                                && self.filter.removes_empty_container(&self.structure_tracker.path[..member_depth], matches!(value_type, ValueType::Object));
+                            if removes_empty_container {
+                                // Decide at the next character except whitespace
+                                let closer = if let ValueType::Object = value_type { CCB_CHR } else { CSB_CHR };
+                                self.pending_removal = Some(self.new_pending_removal(member_depth, Some(closer)));
+                                self.start_member_match(member_depth, pos, *chr);
                                 self.state = ScannerState::WaitingNextKey;
+                                self.wait_key_opener(lexeme, pos);
+                            } else if let (KeyAction::RemoveIfNotContainer | KeyAction::RemoveIfValue, true) = (self.key_action, value_type.is_container()) {
+                                // Keep the container to find the keys in it
+                                if let (KeyAction::RemoveIfNotContainer, true) = (self.key_action, self.prune_empty) {
+                                    // Removed when it is closed without the selected member
+                                    self.hold_ancestor_member(member_depth, pos, *chr);
+                                }
+                                self.rename_key_at(member_depth);
+                                self.state = ScannerState::WaitingNextKey;
+                                self.wait_key_opener(lexeme, pos);
+                            } else if let KeyAction::RemoveIfValue = self.key_action {
+                                // Read the value to decide
+                                let condition_indices = self.filter.value_condition_indices(ConditionTarget::Member, &self.structure_tracker.path);
+                                let length_limit = self.filter.value_length_limit(&condition_indices);
+                                let mut pending_removal = self.new_pending_removal(member_depth, None);
+                                pending_removal.condition_indices = condition_indices;
+                                self.pending_removal = Some(pending_removal);
+                                self.start_member_match(member_depth, pos, *chr);
+                                self.value_range_checker = ValueRangeChecker::recording(value_type, length_limit);
+                                self.value_range_checker.record(&[*chr]);
+                                self.state = ScannerState::CheckingValueRange;
+                            } else if let KeyAction::Redact(redaction_index) = self.key_action {
+                                // Replace only the value
+                                //  - Tracked as a removal without its line not to clean the layout in the value
+                                let replacement = self.filter.replacement(redaction_index).to_vec();
+                                self.push_removal_start(Message::ReplaceFrom((self.next_buffer_index, pos), replacement), None);
+                                // Original value is recorded like the removed one
+                                self.start_member_match(member_depth, pos, *chr);
+                                self.value_range_checker = ValueRangeChecker::new(value_type);
+                                self.state = ScannerState::CheckingValueRange;
+                            } else {
+                                let skip_start_msg = self.skip_start_msg();
+                                self.push_removal_start(skip_start_msg, self.removal_line_start());
+                                self.count_removed_key();
+                                self.start_member_match(member_depth, pos, *chr);
+                                self.value_range_checker = ValueRangeChecker::new(value_type);
+                                self.state = ScannerState::CheckingValueRange;
                             }
-                        } else {
-                            if closed_with_comma {
-                                let skip_end_msg = Message::SkipEndTo((self.next_buffer_index, pos));
-                                self.queue.push(skip_end_msg);
+                        }
+                    },
+                    ScannerState::CheckingValueRange => {
+                        let closing_condition = self.value_range_checker.check_meeting_closing(*chr);
 
-                                self.waiting_condition.key_cand_opener = KeyCandOpener::MeetNonComma;
-                                self.waiting_condition.update_position((self.next_buffer_index, pos)); // Treat comma as non comma
-                                self.state = ScannerState::MeetKeyCandOpener;
-                            } else {
-                                // End signal is deferred
-                                self.skip_end_msg_cache = skip_end_msg;
-                                self.state = ScannerState::FindingNextComma;
+                        if let ClosingCondition::ClosedWithComma(closed_with_comma) = closing_condition {
+                            if let Some(pending_removal) = &self.pending_removal {
+                                if !self.value_range_checker.range_is_to_previous_chr() {
+                                    self.value_range_checker.record(&[*chr]);
+                                }
+                                // Path can be already closed with the object
+                                if self.filter.removes_value(&pending_removal.condition_indices, &self.value_range_checker.value_bytes) {
+                                    self.remove_pending_value();
+                                } else {
+                                    self.keep_pending_value();
+                                    self.state = ScannerState::WaitingNextKey;
+                                    self.wait_key_opener(lexeme, pos);
+                                    break 'state
+                                }
                             }
+                            self.close_value(closed_with_comma, lexeme, pos);
+                        } else if self.pending_removal.is_some() && !self.value_range_checker.record(&[*chr]) {
+                            // Value longer than the conditions is kept
+                            self.keep_pending_value();
+                            self.state = ScannerState::WaitingNextKey;
                         }
-                    };
-                },
-                ScannerState::FindingNextComma => {
-                    match *chr {
-                        SPACE_CHR | TAP_CHR | NEWLINE_CHR | RETURN_CHR => {
-                            // pass
-                        },
-                        COMMA_CHR => {
-                            let skip_end_msg = Message::SkipEndTo((self.next_buffer_index, pos));
-                            self.queue.push(skip_end_msg);
+                    },
+                    ScannerState::FindingNextComma => {
+                        match lexeme {
+                            Lexeme::Whitespace => {
+                                // pass
+                            },
+                            Lexeme::Structural(COMMA_CHR) => {
+                                let skip_end_msg = Message::SkipEndTo((self.next_buffer_index, pos));
+                                self.push_removal_end(skip_end_msg, pos);
 
-                            self.waiting_condition.key_cand_opener = KeyCandOpener::MeetNonComma;
-                            self.waiting_condition.update_position((self.next_buffer_index, pos)); // Treat comma as non comma
-                            self.state = ScannerState::MeetKeyCandOpener;
-                        },
-                        _ => {
-                            self.queue.push(self.skip_end_msg_cache.clone());
+                                if self.structure_tracker.in_object() {
+                                    self.waiting_condition.key_cand_opener = KeyCandOpener::MeetNonComma;
+                                    self.waiting_condition.update_position((self.next_buffer_index, pos), None); // Treat comma as non comma
+                                    self.state = ScannerState::MeetKeyCandOpener;
+                                } else {
+                                    // After the removed element
+                                    self.element_opener.key_cand_opener = KeyCandOpener::MeetNonComma;
+                                    self.element_opener.update_position((self.next_buffer_index, pos), None); // Treat comma as non comma
+                                    self.state = ScannerState::WaitingNextKey;
+                                }
+                            },
+                            _ => {
+                                self.push_removal_end(self.skip_end_msg_cache.clone(), pos);
 
-                            // Act like in WaitingCondition state
-                            let meet_key_opener = self.waiting_condition.check_key_opener(chr);
-                            if meet_key_opener {
-                                self.waiting_condition.update_position((self.next_buffer_index, pos));
-                                self.state = ScannerState::MeetKeyCandOpener;
-                            } else {
+                                // Act like in WaitingCondition state
                                 self.state = ScannerState::WaitingNextKey;
-                            }
-                        },
-                    }
-                },
+                                self.wait_key_opener(lexeme, pos);
+                            },
+                        }
+                    },
+                }
             }
-        });
+            if let Lexeme::Structural(CCB_CHR | CSB_CHR) = lexeme {
+                if !self.close_pending_ancestor(pos) && lexeme == Lexeme::Structural(CCB_CHR) {
+                    self.close_element(pos);
+                }
+            }
+            if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+                let position = (self.next_buffer_index, pos);
+                if let Lexeme::Structural(CCB_CHR | CSB_CHR) = lexeme {
+                    layout_cleaner.close_container(&mut self.queue, position);
+                }
+                layout_cleaner.track(&mut self.queue, *chr, lexeme == Lexeme::Whitespace, position);
+                if let Lexeme::Structural(OCB_CHR | OSB_CHR) = lexeme {
+                    layout_cleaner.open_container(&self.queue, position);
+                }
+            }
+            self.location.advance(*chr);
+            pos += 1;
+        }
 
         // (2) Increase index
         self.next_buffer_index += 1;
+        Ok(())
+    }
+    // Length of the leading characters not changing the state
+    fn skippable_length(&self, bytes: &[u8]) -> usize {
+        if let Some(PendingRemoval { container_closer: Some(_), .. }) = self.pending_removal {
+            // Literal in the container is meaningful
+            0
+        } else if self.structure_tracker.in_string {
+            // Only '"' and '\' are meaningful in string
+            match self.state {
+                _ if self.structure_tracker.escape_next => 0,
+                ScannerState::WaitingNextKey | ScannerState::ConfirmingKey | ScannerState::CheckingValueRange => {
+                    search::find_quote_or_escape(bytes).unwrap_or(bytes.len())
+                },
+                _ => 0,
+            }
+        } else if self.element_expected {
+            // Literal of the element to remove is meaningful
+            0
+        } else if let ScannerState::WaitingNextKey = self.state {
+            // Whitespace, colon and literal are not meaningful
+            search::find_structural(bytes).unwrap_or(bytes.len())
+        } else {
+            0
+        }
+    }
+    fn skip(&mut self, bytes: &[u8], pos: usize) -> Result<(), KeyRemoverError> {
+        if let Some(validator) = self.validator.as_mut() {
+            for (idx, chr) in bytes.iter().enumerate() {
+                if let Err(message) = validator.validate(*chr) {
+                    self.location.advance_all(&bytes[..idx]);
+                    return Err(self.malformed(message))
+                }
+            }
+        }
+        if let ScannerState::ConfirmingKey = self.state {
+            self.key_cache.push_key_bytes(bytes, self.filter.key_trie(), self.key_comparison);
+        }
+        if self.pending_elements.last().is_some_and(|element| element.member_value_reader.is_some()) {
+            for chr in bytes {
+                self.read_member_value(*chr);
+                if self.pending_elements.last().is_some_and(|element| element.member_value_reader.is_none()) {
+                    break
+                }
+            }
+        }
+        if let (ScannerState::CheckingValueRange, Some(_)) = (&self.state, &self.pending_removal) {
+            if !self.value_range_checker.record(bytes) {
+                self.keep_pending_value();
+                self.state = ScannerState::WaitingNextKey;
+            }
+        }
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.track_all(&mut self.queue, bytes, self.structure_tracker.in_string, (self.next_buffer_index, pos));
+        }
+        if self.match_recorder.is_some() {
+            self.feed_match_candidates(bytes);
+        }
+        self.location.advance_all(bytes);
+        Ok(())
+    }
+    // Resolve the deferred state at the end of stream
+    pub fn finish(&mut self) -> Result<(), KeyRemoverError> {
+        if let Some(Err(message)) = self.validator.as_mut().map(|validator| validator.finish()) {
+            return Err(self.malformed(message))
+        }
+        if self.structure_tracker.in_string {
+            return Err(self.malformed("Unexpected end of input in string"))
+        }
+        match self.structure_tracker.containers.last() {
+            Some(ContainerType::Object) => return Err(self.malformed("Unexpected end of input in object")),
+            Some(ContainerType::Array) => return Err(self.malformed("Unexpected end of input in array")),
+            None => {},
+        }
+        if let ScannerState::FindingNextComma = self.state {
+            self.queue.push(self.skip_end_msg_cache.clone());
+        }
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.finish();
+        }
+        self.state = ScannerState::WaitingNextKey;
+        Ok(())
+    }
+    fn malformed(&self, message: &str) -> KeyRemoverError {
+        KeyRemoverError::MalformedJson {
+            message: message.to_string(),
+            offset: self.location.offset,
+            line: self.location.line,
+            column: self.location.column,
+        }
     }
     pub fn key_cand_opener_index(&self) -> ChrIndex {
         self.waiting_condition.opener_position
-    }  
+    }
+    // Data before this index will not be changed by further messages
+    //  - None: All scanned data is resolved
+    pub fn resolved_until(&self) -> Option<ChrIndex> {
+        let resolved_until = self.resolved_until_by_state();
+        // Line of the next removal and the container possibly left empty can be removed together
+        match (resolved_until, self.layout_cleaner.as_ref().and_then(|layout_cleaner| layout_cleaner.held_from(resolved_until))) {
+            (Some(resolved_until), Some(held_from)) => Some(resolved_until.min(held_from)),
+            (resolved_until, held_from) => resolved_until.or(held_from),
+        }
+    }
+    fn resolved_until_by_state(&self) -> Option<ChrIndex> {
+        // Line start of the skip start is before it
+        let held_by_element = self.pending_elements.first().map(|element| element.line_start.unwrap_or(element.skip_start_msg.boundary()));
+        let held_by_ancestor = self.pending_ancestors.first().map(|ancestor| ancestor.line_start.unwrap_or(ancestor.skip_start_msg.boundary()));
+        if let Some(held_from) = held_by_element.into_iter().chain(held_by_ancestor).min() {
+            return Some(held_from)
+        }
+        if self.awaiting_element {
+            return Some(self.element_opener.opener_line_start.unwrap_or(self.element_opener.opener_position))
+        }
+        if let Some(pending_removal) = &self.pending_removal {
+            return Some(pending_removal.line_start.unwrap_or(pending_removal.skip_start_msg.boundary()))
+        }
+        match self.state {
+            ScannerState::WaitingNextKey | ScannerState::CheckingValueRange => {
+                None
+            },
+            ScannerState::FindingNextComma => {
+                Some(self.skip_end_msg_cache.boundary())
+            },
+            ScannerState::MeetKeyCandOpener | ScannerState::ConfirmingKey | ScannerState::DefiningValueType => {
+                Some(self.waiting_condition.opener_line_start.unwrap_or(self.key_cand_opener_index()))
+            },
+        }
+    }
+    // Indices of the buffers skipped by the held messages
+    //  - Messages before the data resolved by the state are only extended by the layout cleaner
+    //  - Skip start is of the released messages if they end in the skip
+    pub fn skipped_buffers(&self, mut skip_start: Option<usize>) -> Vec<Range<usize>> {
+        let resolved_end = self.resolved_until_by_state().map_or(self.next_buffer_index, |resolved_until| resolved_until.0);
+        let mut skipped_buffers = Vec::new();
+        for message in &self.queue {
+            match message {
+                Message::SkipStartFrom(chr_index) | Message::ReplaceFrom(chr_index, _) => skip_start = Some(chr_index.0),
+                skip_end => if let Some(start) = skip_start.take() {
+                    skipped_buffers.push(start + 1..skip_end.boundary().0.min(resolved_end));
+                },
+            }
+        }
+        if let Some(start) = skip_start {
+            skipped_buffers.push(start + 1..resolved_end);
+        }
+        skipped_buffers
+    }
+    // Messages not changed by further data (Messages in the held object can be dropped)
+    pub fn resolved_messages(&mut self) -> std::vec::Drain<'_, Message> {
+        let resolved_until = self.resolved_until_by_state();
+        let held_message_index = self.layout_cleaner.as_ref().and_then(|layout_cleaner| layout_cleaner.held_message_index(resolved_until));
+        let resolved_count = self.pending_elements.first().map_or(self.queue.len(), |element| element.message_mark)
+            .min(self.pending_ancestors.first().map_or(self.queue.len(), |ancestor| ancestor.message_mark))
+            .min(held_message_index.unwrap_or(self.queue.len()));
+        for element in &mut self.pending_elements {
+            element.message_mark -= resolved_count;
+        }
+        for ancestor in &mut self.pending_ancestors {
+            ancestor.message_mark -= resolved_count;
+        }
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.drain_messages(resolved_count);
+        }
+        self.queue.drain(..resolved_count)
+    }
+    fn start_element(&mut self, pos: usize) {
+        // Path of the element (Object is already pushed)
+        let path = &self.structure_tracker.path;
+        let in_array = matches!(self.structure_tracker.containers.iter().rev().nth(1), Some(ContainerType::Array));
+        if !in_array || !self.filter.may_remove_element(&path[..path.len()-1]) {
+            return
+        }
+        if self.pending_elements.last().is_some_and(|element| element.matched) {
+            // Removed with the outer object
+            return
+        }
+        let (skip_start_msg, line_start, opener_is_comma) = self.element_skip_start(pos);
+        let match_candidate = self.match_recorder.as_ref().map(|match_recorder| {
+            let mut match_candidate = match_recorder.new_candidate(None, &path[..path.len()-1], (self.next_buffer_index, pos), (self.next_buffer_index, pos));
+            match_candidate.feed(&[OCB_CHR]);
+            match_candidate
+        });
+        self.pending_elements.push(PendingElement {
+            skip_start_msg,
+            line_start,
+            opener_is_comma,
+            member_depth: path.len(),
+            message_mark: self.queue.len(),
+            member_value_reader: None,
+            matched: false,
+            match_candidate,
+        });
+    }
+    // Skip start of the element from the comma before it or from the element
+    fn element_skip_start(&self, pos: usize) -> (Message, Option<ChrIndex>, bool) {
+        if self.element_opener.key_cand_opener_is_comma() {
+            (Message::SkipStartFrom(self.element_opener.opener_position), self.element_opener.opener_line_start, true)
+        } else {
+            (Message::SkipStartFrom((self.next_buffer_index, pos)), self.blank_line_start(), false)
+        }
+    }
+    // Remove the element or hold the container element until the selected member is found in it
+    //  - Container element which can have the selected member is streamed unless empty containers are pruned
+    fn start_unselected_element(&mut self, element_skip_start: (Message, Option<ChrIndex>, bool), lexeme: Lexeme, pos: usize, chr: u8) {
+        let (skip_start_msg, line_start, opener_is_comma) = element_skip_start;
+        // Path of the element (Container is already pushed)
+        let path = &self.structure_tracker.path;
+        let is_ancestor = matches!(lexeme, Lexeme::Structural(OCB_CHR | OSB_CHR)) && self.filter.may_have_selected(&path[..path.len()-1]);
+        let value_type = match lexeme {
+            _ if is_ancestor && !self.prune_empty => return,
+            _ if is_ancestor => {
+                let match_candidate = self.match_recorder.as_ref().map(|match_recorder| {
+                    let mut match_candidate = match_recorder.new_candidate(None, &path[..path.len()-1], (self.next_buffer_index, pos), (self.next_buffer_index, pos));
+                    match_candidate.feed(&[chr]);
+                    match_candidate
+                });
+                self.pending_ancestors.push(PendingAncestor {
+                    skip_start_msg,
+                    line_start,
+                    opener_is_comma,
+                    depth: path.len(),
+                    message_mark: self.queue.len(),
+                    key: None,
+                    match_candidate,
+                });
+                return
+            },
+            Lexeme::Structural(OCB_CHR) => ValueType::Object,
+            Lexeme::Structural(OSB_CHR) => ValueType::Array,
+            Lexeme::StringOpen => ValueType::String,
+            Lexeme::Literal => ValueType::Others,
+            _ => return,
+        };
+        if value_type.is_container() && self.pending_elements.last().is_some_and(|element| element.member_depth == path.len()) {
+            // Object to check its member is removed as a whole
+            self.pending_elements.pop();
+        }
+        let element_depth = path.len() - usize::from(value_type.is_container());
+        self.push_removal_start(skip_start_msg, line_start);
+        if let Some(match_recorder) = self.match_recorder.as_mut() {
+            let mut element = match_recorder.new_candidate(None, &self.structure_tracker.path[..element_depth], (self.next_buffer_index, pos), (self.next_buffer_index, pos));
+            element.feed(&[chr]);
+            match_recorder.member = Some(element);
+        }
+        self.removing_element = Some(opener_is_comma);
+        self.value_range_checker = ValueRangeChecker::new(value_type);
+        self.state = ScannerState::CheckingValueRange;
+    }
+    // Hold the container of the member not selected in keep mode
+    fn hold_ancestor_member(&mut self, member_depth: usize, pos: usize, chr: u8) {
+        let key_string = self.key_cache.original_key_string(self.key_comparison);
+        let match_candidate = self.match_recorder.as_ref().map(|match_recorder| {
+            let path = &self.structure_tracker.path[..member_depth];
+            let mut match_candidate = match_recorder.new_candidate(Some(key_string.clone()), path, self.key_cache.dq_start_position, (self.next_buffer_index, pos));
+            match_candidate.feed(&[chr]);
+            match_candidate
+        });
+        self.pending_ancestors.push(PendingAncestor {
+            skip_start_msg: self.skip_start_msg(),
+            line_start: self.removal_line_start(),
+            opener_is_comma: self.waiting_condition.key_cand_opener_is_comma(),
+            depth: self.structure_tracker.path.len(),
+            message_mark: self.queue.len(),
+            key: Some(key_string),
+            match_candidate,
+        });
+    }
+    // Remove the held container if the selected member is not found in it
+    fn close_pending_ancestor(&mut self, pos: usize) -> bool {
+        let closed = self.pending_ancestors.last().is_some_and(|ancestor| self.structure_tracker.path.len() + 1 == ancestor.depth);
+        if !closed {
+            return false
+        }
+        let ancestor = match self.pending_ancestors.pop() {
+            Some(ancestor) => ancestor,
+            None => return false,
+        };
+        // Object held to check its member is removed together
+        if self.pending_elements.last().is_some_and(|element| element.member_depth == ancestor.depth) {
+            self.pending_elements.pop();
+        }
+        if let Some(key_string) = ancestor.key {
+            *self.stats.removed_keys.entry(key_string).or_default() += 1;
+        }
+        if let (Some(match_recorder), Some(match_candidate)) = (self.match_recorder.as_mut(), ancestor.match_candidate) {
+            match_recorder.record(match_candidate, (self.next_buffer_index, pos + 1));
+        }
+        self.remove_held_container(ancestor.message_mark, ancestor.skip_start_msg, ancestor.line_start, ancestor.opener_is_comma, pos);
+        true
+    }
+    fn start_reading_member_value(&mut self) {
+        let path = &self.structure_tracker.path;
+        if let Some(element) = self.pending_elements.last_mut() {
+            if path.len() == element.member_depth && !element.matched {
+                let condition_indices = self.filter.value_condition_indices(ConditionTarget::Element, path);
+                if !condition_indices.is_empty() {
+                    let length_limit = self.filter.value_length_limit(&condition_indices);
+                    element.member_value_reader = Some(MemberValueReader::new(condition_indices, length_limit));
+                }
+            }
+        }
+    }
+    fn read_member_value(&mut self, chr: u8) {
+        if let Some(element) = self.pending_elements.last_mut() {
+            if let Some(reader) = element.member_value_reader.as_mut() {
+                match reader.read(chr) {
+                    ReadingState::Reading => {},
+                    ReadingState::Read => {
+                        element.matched = self.filter.removes_value(&reader.condition_indices, reader.value_bytes());
+                        element.member_value_reader = None;
+                    },
+                    ReadingState::Unreadable => {
+                        element.member_value_reader = None;
+                    },
+                }
+            }
+        }
+    }
+    // Remove the held object if its member meets the condition
+    fn close_element(&mut self, pos: usize) {
+        let closed = self.pending_elements.last().is_some_and(|element| self.structure_tracker.path.len() + 1 == element.member_depth);
+        if !closed {
+            return
+        }
+        let element = match self.pending_elements.pop() {
+            Some(element) if element.matched => element,
+            _ => return,
+        };
+        self.stats.removed_elements += 1;
+        if let (Some(match_recorder), Some(match_candidate)) = (self.match_recorder.as_mut(), element.match_candidate) {
+            match_recorder.record(match_candidate, (self.next_buffer_index, pos + 1));
+        }
+        self.remove_held_container(element.message_mark, element.skip_start_msg, element.line_start, element.opener_is_comma, pos);
+    }
+    // Replace the messages in the held container closed at the position with its removal
+    fn remove_held_container(&mut self, message_mark: usize, skip_start_msg: Message, line_start: Option<ChrIndex>, opener_is_comma: bool, pos: usize) {
+        self.queue.truncate(message_mark);
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.truncate_messages(message_mark);
+        }
+        self.push_removal_start(skip_start_msg, line_start);
+        let skip_end_msg = Message::SkipEndTo((self.next_buffer_index, pos));
+        if opener_is_comma {
+            self.push_removal_end(skip_end_msg, pos);
+            self.state = ScannerState::WaitingNextKey;
+        } else {
+            // Comma after the element is removed
+            self.skip_end_msg_cache = skip_end_msg;
+            self.state = ScannerState::FindingNextComma;
+        }
+    }
+    fn skip_start_msg(&self) -> Message {
+        if self.waiting_condition.key_cand_opener_is_comma() {
+            Message::SkipStartFrom(self.waiting_condition.opener_position)
+        } else {
+            Message::SkipStartFrom(self.key_cache.dq_start_position)
+        }
+    }
+    // Start of the line of the skip start if only whitespace is before it in the line
+    fn removal_line_start(&self) -> Option<ChrIndex> {
+        if self.waiting_condition.key_cand_opener_is_comma() {
+            self.waiting_condition.opener_line_start
+        } else {
+            self.key_cache.dq_line_start
+        }
+    }
+    fn blank_line_start(&self) -> Option<ChrIndex> {
+        self.layout_cleaner.as_ref().and_then(|layout_cleaner| layout_cleaner.blank_line_start())
+    }
+    fn push_removal_start(&mut self, skip_start_msg: Message, line_start: Option<ChrIndex>) {
+        self.queue.push(skip_start_msg);
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.start_removal(&self.queue, line_start);
+        }
+    }
+    fn push_removal_end(&mut self, skip_end_msg: Message, pos: usize) {
+        self.queue.push(skip_end_msg);
+        if let Some(layout_cleaner) = self.layout_cleaner.as_mut() {
+            layout_cleaner.end_removal(&mut self.queue, (self.next_buffer_index, pos));
+        }
+    }
+    fn new_pending_removal(&self, member_depth: usize, container_closer: Option<u8>) -> PendingRemoval {
+        PendingRemoval {
+            skip_start_msg: self.skip_start_msg(),
+            line_start: self.removal_line_start(),
+            key_opener_is_comma: self.waiting_condition.key_cand_opener_is_comma(),
+            opener_position: self.waiting_condition.opener_position,
+            opener_line_start: self.waiting_condition.opener_line_start,
+            member_depth,
+            container_closer,
+            condition_indices: Vec::new(),
+        }
+    }
+    // Value meets the condition
+    fn remove_pending_value(&mut self) {
+        if let Some(pending_removal) = self.pending_removal.take() {
+            self.push_removal_start(pending_removal.skip_start_msg, pending_removal.line_start);
+            self.count_removed_key();
+            // Opener can be changed in the container
+            self.waiting_condition.key_cand_opener = if pending_removal.key_opener_is_comma {
+                KeyCandOpener::MeetComma
+            } else {
+                KeyCandOpener::MeetNonComma
+            };
+            self.waiting_condition.update_position(pending_removal.opener_position, pending_removal.opener_line_start);
+        }
+    }
+    // Value does not meet the condition
+    fn keep_pending_value(&mut self) {
+        if let Some(pending_removal) = self.pending_removal.take() {
+            self.rename_key_at(pending_removal.member_depth);
+        }
+        if let Some(match_recorder) = self.match_recorder.as_mut() {
+            match_recorder.member = None;
+        }
+    }
+    // Skip to the end of the removed or redacted value and find the next key
+    fn close_value(&mut self, closed_with_comma: bool, lexeme: Lexeme, pos: usize) {
+        // (1) Get value end position
+        let value_is_to_previous = self.value_range_checker.range_is_to_previous_chr();
+        let skip_end_msg = if value_is_to_previous {
+            Message::SkipEndPreviousTo((self.next_buffer_index, pos))
+        } else {
+            Message::SkipEndTo((self.next_buffer_index, pos))
+        };
+
+        if let Some(match_recorder) = self.match_recorder.as_mut() {
+            if let Some(member) = match_recorder.member.take() {
+                match_recorder.record(member, skip_end_msg.boundary());
+            }
+        }
+
+        // (2) Next state
+        let key_opener_is_comma = self.waiting_condition.key_cand_opener_is_comma();
+        if let Some(element_opener_is_comma) = self.removing_element.take() {
+            if element_opener_is_comma || (value_is_to_previous && lexeme == Lexeme::Structural(CSB_CHR)) {
+                self.push_removal_end(skip_end_msg, pos);
+                self.state = ScannerState::WaitingNextKey;
+            } else if closed_with_comma {
+                self.push_removal_end(Message::SkipEndTo((self.next_buffer_index, pos)), pos);
+                self.element_opener.key_cand_opener = KeyCandOpener::MeetNonComma;
+                self.element_opener.update_position((self.next_buffer_index, pos), None); // Treat comma as non comma
+                self.state = ScannerState::WaitingNextKey;
+            } else {
+                // Comma after the element is removed
+                self.skip_end_msg_cache = skip_end_msg;
+                self.state = ScannerState::FindingNextComma;
+            }
+        } else if let KeyAction::Redact(_) = self.key_action {
+            // Comma is remained
+            self.push_removal_end(skip_end_msg, pos);
+
+            self.state = ScannerState::WaitingNextKey;
+            self.wait_key_opener(lexeme, pos);
+        } else if key_opener_is_comma {
+            if closed_with_comma {
+                self.push_removal_end(skip_end_msg, pos);
+
+                self.waiting_condition.key_cand_opener = KeyCandOpener::MeetComma;
+                self.waiting_condition.update_position((self.next_buffer_index, pos), None);
+                self.state = ScannerState::MeetKeyCandOpener;
+            } else {
+                self.push_removal_end(skip_end_msg, pos);
+
+                self.state = ScannerState::WaitingNextKey;
+            }
+        } else {
+            if closed_with_comma {
+                let skip_end_msg = Message::SkipEndTo((self.next_buffer_index, pos));
+                self.push_removal_end(skip_end_msg, pos);
+
+                self.waiting_condition.key_cand_opener = KeyCandOpener::MeetNonComma;
+                self.waiting_condition.update_position((self.next_buffer_index, pos), None); // Treat comma as non comma
+                self.state = ScannerState::MeetKeyCandOpener;
+            } else if value_is_to_previous && matches!(lexeme, Lexeme::Structural(CCB_CHR | CSB_CHR)) {
+                // Only member is closed with the object
+                self.push_removal_end(skip_end_msg, pos);
+
+                self.state = ScannerState::WaitingNextKey;
+            } else {
+                // End signal is deferred
+                self.skip_end_msg_cache = skip_end_msg;
+                self.state = ScannerState::FindingNextComma;
+            }
+        }
+    }
+    fn start_member_match(&mut self, member_depth: usize, pos: usize, chr: u8) {
+        if let Some(match_recorder) = self.match_recorder.as_mut() {
+            let key_string = self.key_cache.original_key_string(self.key_comparison);
+            let path = &self.structure_tracker.path[..member_depth];
+            let mut member = match_recorder.new_candidate(Some(key_string), path, self.key_cache.dq_start_position, (self.next_buffer_index, pos));
+            member.feed(&[chr]);
+            match_recorder.member = Some(member);
+        }
+    }
+    fn feed_match_candidates(&mut self, bytes: &[u8]) {
+        if let Some(member) = self.match_recorder.as_mut().and_then(|match_recorder| match_recorder.member.as_mut()) {
+            member.feed(bytes);
+        }
+        for match_candidate in self.pending_elements.iter_mut().filter_map(|element| element.match_candidate.as_mut()) {
+            match_candidate.feed(bytes);
+        }
+        for match_candidate in self.pending_ancestors.iter_mut().filter_map(|ancestor| ancestor.match_candidate.as_mut()) {
+            match_candidate.feed(bytes);
+        }
+    }
+    fn count_removed_key(&mut self) {
+        let key_string = self.key_cache.original_key_string(self.key_comparison);
+        *self.stats.removed_keys.entry(key_string).or_default() += 1;
+    }
+    fn rename_key(&mut self) {
+        self.rename_key_at(self.structure_tracker.path.len());
+    }
+    fn rename_key_at(&mut self, member_depth: usize) {
+        if let Some(PathSegment::Key(key_string)) = self.structure_tracker.path.get(member_depth.wrapping_sub(1)) {
+            if let Some(quoted_new_key) = self.filter.renamed_key(key_string) {
+                self.queue.push(Message::ReplaceFrom(self.key_cache.dq_start_position, quoted_new_key.to_vec()));
+                self.queue.push(Message::SkipEndTo(self.key_cache.dq_end_position));
+            }
+        }
+    }
+    fn wait_key_opener(&mut self, lexeme: Lexeme, pos: usize) {
+        let in_object = self.structure_tracker.in_object();
+        let meet_key_opener = self.waiting_condition.check_key_opener(lexeme, in_object);
+        if meet_key_opener {
+            self.waiting_condition.update_position((self.next_buffer_index, pos), self.blank_line_start());
+            self.state = ScannerState::MeetKeyCandOpener;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+impl Default for Location {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+impl Location {
+    fn advance_all(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len();
+        match search::find_newlines(bytes) {
+            Some((count, last)) => {
+                self.line += count;
+                self.column = bytes.len() - last;
+            },
+            None => {
+                self.column += bytes.len();
+            },
+        }
+    }
+    fn advance(&mut self, chr: u8) {
+        self.offset += 1;
+        if chr == NEWLINE_CHR {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct StructureTracker {
+    in_string: bool,
+    escape_next: bool,
+    containers: Vec<ContainerType>,
+    path: Vec<PathSegment>, // Current key or index of each container
+}
+#[derive(Debug)]
+enum ContainerType {
+    Object,
+    Array,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lexeme {
+    StringOpen,
+    StringContent,
+    StringClose,
+    Structural(u8), // { } [ ] , :
+    Whitespace,
+    Literal, // Null, Number, Bool
+}
+impl StructureTracker {
+    fn track(&mut self, chr: u8) -> Result<Lexeme, &'static str> {
+        if self.in_string {
+            if self.escape_next {
+                self.escape_next = false;
+                return Ok(Lexeme::StringContent)
+            }
+            let lexeme = match chr {
+                ESCAPE_CHR => {
+                    self.escape_next = true;
+                    Lexeme::StringContent
+                },
+                DQ_CHR => {
+                    self.in_string = false;
+                    Lexeme::StringClose
+                },
+                _ => {
+                    Lexeme::StringContent
+                },
+            };
+            Ok(lexeme)
+        } else {
+            let lexeme = match chr {
+                DQ_CHR => {
+                    self.in_string = true;
+                    Lexeme::StringOpen
+                },
+                OCB_CHR => {
+                    self.containers.push(ContainerType::Object);
+                    self.path.push(PathSegment::Key(String::new()));
+                    Lexeme::Structural(chr)
+                },
+                OSB_CHR => {
+                    self.containers.push(ContainerType::Array);
+                    self.path.push(PathSegment::Index(0));
+                    Lexeme::Structural(chr)
+                },
+                CCB_CHR => {
+                    match self.containers.pop() {
+                        Some(ContainerType::Object) => {},
+                        Some(ContainerType::Array) => return Err("Array is closed with '}'"),
+                        None => return Err("Unexpected '}'"),
+                    }
+                    self.path.pop();
+                    Lexeme::Structural(chr)
+                },
+                CSB_CHR => {
+                    match self.containers.pop() {
+                        Some(ContainerType::Array) => {},
+                        Some(ContainerType::Object) => return Err("Object is closed with ']'"),
+                        None => return Err("Unexpected ']'"),
+                    }
+                    self.path.pop();
+                    Lexeme::Structural(chr)
+                },
+                COMMA_CHR => {
+                    if let Some(PathSegment::Index(index)) = self.path.last_mut() {
+                        *index += 1;
+                    }
+                    Lexeme::Structural(chr)
+                },
+                COLON_CHR => {
+                    Lexeme::Structural(chr)
+                },
+                SPACE_CHR | TAP_CHR | NEWLINE_CHR | RETURN_CHR => {
+                    Lexeme::Whitespace
+                },
+                _ => {
+                    Lexeme::Literal
+                },
+            };
+            Ok(lexeme)
+        }
+    }
+    fn in_object(&self) -> bool {
+        matches!(self.containers.last(), Some(ContainerType::Object))
+    }
+    fn update_key(&mut self, key_string: String) {
+        if let Some(segment) = self.path.last_mut() {
+            *segment = PathSegment::Key(key_string);
+        }
+    }
 }
 
 #[derive(Debug)]
 struct WaitingCondition {
     key_cand_opener: KeyCandOpener,
     opener_position: ChrIndex,
+    opener_line_start: Option<ChrIndex>, // Start of the line with only whitespace before the opener
 }
 #[derive(Debug)]
 enum KeyCandOpener {
@@ -229,17 +1133,18 @@ impl Default for WaitingCondition {
         Self {
             key_cand_opener: KeyCandOpener::MeetNonComma,
             opener_position: (0, 0),
+            opener_line_start: None,
         }
     }
 }
 impl WaitingCondition {
-    fn check_key_opener(&mut self, chr: &u8) -> bool {
-        match *chr {
-            OCB_CHR | OSB_CHR => {
+    fn check_key_opener(&mut self, lexeme: Lexeme, in_object: bool) -> bool {
+        match lexeme {
+            Lexeme::Structural(OCB_CHR) => {
                 self.key_cand_opener = KeyCandOpener::MeetNonComma;
                 true
             },
-            COMMA_CHR => {
+            Lexeme::Structural(COMMA_CHR) if in_object => {
                 self.key_cand_opener = KeyCandOpener::MeetComma;
                 true
             },
@@ -248,45 +1153,148 @@ impl WaitingCondition {
             },
         }
     }
-    fn update_position(&mut self, opener_position: ChrIndex) {
+    fn update_position(&mut self, opener_position: ChrIndex, opener_line_start: Option<ChrIndex>) {
         self.opener_position = opener_position;
+        self.opener_line_start = opener_line_start;
     }
     fn key_cand_opener_is_comma(&self) -> bool {
-        match self.key_cand_opener {
-            KeyCandOpener::MeetComma => true,
-            _ => false,
+        matches!(self.key_cand_opener, KeyCandOpener::MeetComma)
+    }
+}
+
+// Statistics of the document (Cleared with the reset)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    pub removed_keys: BTreeMap<String, usize>, // Count of removed members by key
+    pub removed_elements: usize, // Count of objects removed from arrays by their members
+    pub max_depth: usize, // Maximum nesting depth of containers
+}
+
+// Removal of the member decided after reading the value
+#[derive(Debug)]
+struct PendingRemoval {
+    skip_start_msg: Message,
+    line_start: Option<ChrIndex>, // Line start of the skip start in the blank line
+    key_opener_is_comma: bool,
+    opener_position: ChrIndex,
+    opener_line_start: Option<ChrIndex>,
+    member_depth: usize, // Length of the path to the member
+    container_closer: Option<u8>, // Closer of the empty container to remove
+    condition_indices: Vec<usize>, // Value conditions for the scalar value
+}
+
+// Object in array held until it is closed
+#[derive(Debug)]
+struct PendingElement {
+    skip_start_msg: Message,
+    line_start: Option<ChrIndex>, // Line start of the skip start in the blank line
+    opener_is_comma: bool,
+    member_depth: usize, // Length of the path to its members
+    message_mark: usize, // Messages in the object start at this index of the queue
+    member_value_reader: Option<MemberValueReader>,
+    matched: bool,
+    match_candidate: Option<MatchCandidate>, // Listed if it is removed in dry-run mode
+}
+
+// Reader of the scalar value of the member after the key
+#[derive(Debug)]
+struct MemberValueReader {
+    condition_indices: Vec<usize>,
+    length_limit: usize,
+    value_type_definer: ValueTypeDefiner,
+    value_range_checker: Option<ValueRangeChecker>,
+}
+enum ReadingState {
+    Reading,
+    Read,
+    Unreadable, // Container, too long value or malformed
+}
+impl MemberValueReader {
+    fn new(condition_indices: Vec<usize>, length_limit: usize) -> Self {
+        Self {
+            condition_indices,
+            length_limit,
+            value_type_definer: ValueTypeDefiner::default(),
+            value_range_checker: None,
         }
     }
+    fn read(&mut self, chr: u8) -> ReadingState {
+        match self.value_range_checker.as_mut() {
+            None => {
+                match self.value_type_definer.define_value_type(&chr) {
+                    Ok(None) => ReadingState::Reading,
+                    Ok(Some(value_type)) if !value_type.is_container() => {
+                        let mut value_range_checker = ValueRangeChecker::recording(value_type, self.length_limit);
+                        let within_limit = value_range_checker.record(&[chr]);
+                        self.value_range_checker = Some(value_range_checker);
+                        if within_limit { ReadingState::Reading } else { ReadingState::Unreadable }
+                    },
+                    _ => ReadingState::Unreadable,
+                }
+            },
+            Some(value_range_checker) => {
+                match value_range_checker.check_meeting_closing(chr) {
+                    ClosingCondition::ClosedWithComma(_) => {
+                        if !value_range_checker.range_is_to_previous_chr() {
+                            value_range_checker.record(&[chr]);
+                        }
+                        ReadingState::Read
+                    },
+                    ClosingCondition::ClosedYet if value_range_checker.record(&[chr]) => ReadingState::Reading,
+                    ClosingCondition::ClosedYet => ReadingState::Unreadable,
+                }
+            },
+        }
+    }
+    fn value_bytes(&self) -> &[u8] {
+        self.value_range_checker.as_ref().map_or(&[], |value_range_checker| &value_range_checker.value_bytes)
+    }
 }
 
 #[derive(Debug)]
 struct KeyCache {
-    key_string: String,
+    key_bytes: Vec<u8>, // Raw bytes between double quotes
+    key_prefix: KeyPrefix,
     dq_start_position: ChrIndex,
+    dq_end_position: ChrIndex,
+    dq_line_start: Option<ChrIndex>, // Start of the line with only whitespace before the key
     escape_next: bool,
+    keep_rejected: bool, // Bytes of the rejected key are cached to count its removal
+}
+// Whether the key read so far can be one of the keys in the filter
+#[derive(Debug, Clone, Copy)]
+enum KeyPrefix {
+    Known(usize), // Node of key trie
+    Rejected, // Key is not one of the keys in the filter (Bytes are cached only if it can be removed)
+    Unchecked, // Without the key trie or with the escape to decode
 }
 impl Default for KeyCache {
     fn default() -> Self {
-        Self::new((0,0))
+        Self::new((0,0), false, false)
     }
 }
 impl KeyCache {
-    fn new(dq_position: ChrIndex) -> Self {
+    fn new(dq_position: ChrIndex, check_prefix: bool, keep_rejected: bool) -> Self {
         Self {
-            key_string: String::new(),
+            key_bytes: Vec::new(),
+            key_prefix: if check_prefix { KeyPrefix::Known(KeyTrie::ROOT) } else { KeyPrefix::Unchecked },
             dq_start_position: dq_position,
+            dq_end_position: dq_position,
+            dq_line_start: None,
             escape_next: false,
+            keep_rejected,
         }
     }
-    fn confirm_key(&mut self, chr: &u8) -> bool {
+    fn confirm_key(&mut self, chr: &u8, key_trie: Option<&KeyTrie>, key_comparison: KeyComparison) -> bool {
         if self.escape_next {
             // Push always
-            self.key_string.push(*chr as char);
+            self.push_key_bytes(&[*chr], key_trie, key_comparison);
             self.escape_next = false;
             false
         } else {
             match *chr {
                 ESCAPE_CHR => {
+                    self.push_key_bytes(&[*chr], key_trie, key_comparison);
                     self.escape_next = true;
                     false
                 },
@@ -295,12 +1303,134 @@ impl KeyCache {
                 },
                 _ => {
                     // All other chr to key
-                    self.key_string.push(*chr as char);
+                    self.push_key_bytes(&[*chr], key_trie, key_comparison);
                     false
                 },
             }
         }
     }
+    fn push_key_bytes(&mut self, bytes: &[u8], key_trie: Option<&KeyTrie>, key_comparison: KeyComparison) {
+        if let (KeyPrefix::Known(mut node), Some(key_trie)) = (self.key_prefix, key_trie) {
+            for chr in bytes {
+                if *chr == ESCAPE_CHR && key_comparison == KeyComparison::Decoded {
+                    // Decoded key can be different
+                    self.key_prefix = KeyPrefix::Unchecked;
+                    break
+                }
+                match key_trie.next(node, *chr) {
+                    Some(next_node) => node = next_node,
+                    None => {
+                        self.key_prefix = KeyPrefix::Rejected;
+                        break
+                    },
+                }
+            }
+            if let KeyPrefix::Known(_) = self.key_prefix {
+                self.key_prefix = KeyPrefix::Known(node);
+            }
+        }
+        match self.key_prefix {
+            KeyPrefix::Rejected if !self.keep_rejected => self.key_bytes.clear(),
+            _ => self.key_bytes.extend_from_slice(bytes),
+        }
+    }
+    // Rejected key is empty
+    fn key_string(&self, key_comparison: KeyComparison) -> String {
+        match self.key_prefix {
+            KeyPrefix::Rejected => String::new(),
+            _ => self.original_key_string(key_comparison),
+        }
+    }
+    // Key in JSON even if it is rejected
+    fn original_key_string(&self, key_comparison: KeyComparison) -> String {
+        match key_comparison {
+            KeyComparison::Decoded => decode_json_string(&self.key_bytes),
+            KeyComparison::RawBytes => String::from_utf8_lossy(&self.key_bytes).into_owned(),
+        }
+    }
+}
+
+// How to compare the keys in JSON with the keys to find
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyComparison {
+    Decoded, // Compare the value of JSON string (e.g. "\u0061" is "a")
+    RawBytes, // Compare the bytes between double quotes as they are
+}
+
+// Unescape the bytes of JSON string (Invalid sequence is remained or replaced with U+FFFD)
+fn decode_json_string(raw_bytes: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(raw_bytes.len());
+    let mut pos = 0;
+    while pos < raw_bytes.len() {
+        if raw_bytes[pos] != ESCAPE_CHR || pos + 1 == raw_bytes.len() {
+            decoded.push(raw_bytes[pos]);
+            pos += 1;
+            continue
+        }
+        let unescaped = match raw_bytes[pos + 1] {
+            b'"' => Some(b'"'),
+            b'\\' => Some(b'\\'),
+            b'/' => Some(b'/'),
+            b'b' => Some(0x08),
+            b'f' => Some(0x0c),
+            b'n' => Some(b'\n'),
+            b'r' => Some(b'\r'),
+            b't' => Some(b'\t'),
+            _ => None,
+        };
+        if let Some(byte) = unescaped {
+            decoded.push(byte);
+            pos += 2;
+            continue
+        }
+        // \uXXXX with surrogate pair
+        match parse_hex4(&raw_bytes[pos + 1..]) {
+            Some(high @ 0xD800..=0xDBFF) => {
+                let low = if raw_bytes.get(pos + 6) == Some(&ESCAPE_CHR) {
+                    parse_hex4(&raw_bytes[pos + 7..])
+                } else {
+                    None
+                };
+                match low {
+                    Some(low @ 0xDC00..=0xDFFF) => {
+                        let code_point = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                        push_char(&mut decoded, char::from_u32(code_point));
+                        pos += 12;
+                    },
+                    _ => {
+                        push_char(&mut decoded, None);
+                        pos += 6;
+                    },
+                }
+            },
+            Some(code_point) => {
+                push_char(&mut decoded, char::from_u32(code_point));
+                pos += 6;
+            },
+            None => {
+                // Unknown escape
+                decoded.push(raw_bytes[pos]);
+                pos += 1;
+            },
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(string) => string,
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    }
+}
+// Parse "uXXXX"
+fn parse_hex4(bytes: &[u8]) -> Option<u32> {
+    if bytes.len() < 5 || bytes[0] != b'u' {
+        return None
+    }
+    let hex = std::str::from_utf8(&bytes[1..5]).ok()?;
+    u32::from_str_radix(hex, 16).ok()
+}
+fn push_char(decoded: &mut Vec<u8>, chr: Option<char>) {
+    let chr = chr.unwrap_or(char::REPLACEMENT_CHARACTER);
+    let mut utf8 = [0; 4];
+    decoded.extend_from_slice(chr.encode_utf8(&mut utf8).as_bytes());
 }
 
 #[derive(Debug, Default)]
@@ -311,37 +1441,40 @@ impl ValueTypeDefiner {
     fn init(&mut self) {
         self.meet_colon = false;
     }
-    fn define_value_type(&mut self, chr: &u8) -> Option<ValueType> {
+    fn define_value_type(&mut self, chr: &u8) -> Result<Option<ValueType>, &'static str> {
         if self.meet_colon {
             match *chr {
                 OCB_CHR => {
-                    Some(ValueType::Object)
+                    Ok(Some(ValueType::Object))
                 },
                 OSB_CHR => {
-                    Some(ValueType::Array)
+                    Ok(Some(ValueType::Array))
                 },
                 DQ_CHR => {
-                    Some(ValueType::String)
+                    Ok(Some(ValueType::String))
                 },
                 SPACE_CHR | TAP_CHR | NEWLINE_CHR | RETURN_CHR => {
-                    None
+                    Ok(None)
+                },
+                CCB_CHR | CSB_CHR | COMMA_CHR | COLON_CHR => {
+                    Err("Value is missing after the colon")
                 },
                 _ => {
-                    Some(ValueType::Others)
+                    Ok(Some(ValueType::Others))
                 },
             }
         } else {
             // Waiting colon
             match *chr {
                 SPACE_CHR | TAP_CHR | NEWLINE_CHR | RETURN_CHR => {
-                    None
+                    Ok(None)
                 },
                 COLON_CHR => {
                     self.meet_colon = true;
-                    None
+                    Ok(None)
                 },
                 _ => {
-                    panic!("Colon is not right after the Key") // TODO: To debug, remove later
+                    Err("Colon is not right after the key")
                 }
             }
         }
@@ -352,7 +1485,10 @@ impl ValueTypeDefiner {
 struct ValueRangeChecker {
     value_type: ValueType,
     hierarchy: usize,
+    in_string: bool, // In the string nested in object or array
     escape_next: bool,
+    value_bytes: Vec<u8>, // Bytes of the value to inspect
+    length_limit: usize,
 }
 impl Default for ValueRangeChecker {
     fn default() -> Self {
@@ -364,11 +1500,40 @@ impl ValueRangeChecker {
         Self {
             value_type,
             hierarchy: 0,
+            in_string: false,
             escape_next: false,
+            value_bytes: Vec::new(),
+            length_limit: 0,
         }
     }
+    fn recording(value_type: ValueType, length_limit: usize) -> Self {
+        Self {
+            length_limit,
+            ..Self::new(value_type)
+        }
+    }
+    // False if the value is longer than the limit (Bytes over the limit are not kept)
+    fn record(&mut self, bytes: &[u8]) -> bool {
+        let room = (self.length_limit + 1).saturating_sub(self.value_bytes.len());
+        self.value_bytes.extend_from_slice(&bytes[..bytes.len().min(room)]);
+        self.value_bytes.len() <= self.length_limit
+    }
     fn check_meeting_closing(&mut self, chr: u8) -> ClosingCondition {
-        if !self.escape_next {
+        if self.in_string && !self.escape_next {
+            // Brackets in the nested string are not counted
+            match chr {
+                DQ_CHR => {
+                    self.in_string = false;
+                },
+                ESCAPE_CHR => {
+                    self.escape_next = true;
+                },
+                _ => {
+                    // pass
+                },
+            }
+            ClosingCondition::ClosedYet
+        } else if !self.escape_next {
             match chr {
                 // Curly Bracket
                 OCB_CHR => {
@@ -436,6 +1601,7 @@ impl ValueRangeChecker {
                             ClosingCondition::ClosedWithComma(false)
                         },
                         _ => {
+                            self.in_string = true;
                             ClosingCondition::ClosedYet
                         },
                     }
@@ -476,10 +1642,7 @@ impl ValueRangeChecker {
         }
     }
     fn range_is_to_previous_chr(&self) -> bool {
-        match self.value_type {
-            ValueType::Others => true,
-            _ => false,
-        }
+        matches!(self.value_type, ValueType::Others)
     }
 }
 
@@ -490,8 +1653,25 @@ enum ValueType {
     Array,
     Others, // Null, Number, Bool
 }
+impl ValueType {
+    fn is_container(&self) -> bool {
+        matches!(self, Self::Object | Self::Array)
+    }
+}
 
 enum ClosingCondition {
     ClosedYet,
     ClosedWithComma(bool),
 }
+
+// Container held in keep mode until the selected member is found in it
+#[derive(Debug)]
+struct PendingAncestor {
+    skip_start_msg: Message,
+    line_start: Option<ChrIndex>, // Line start of the skip start in the blank line
+    opener_is_comma: bool,
+    depth: usize, // Length of the path in the container
+    message_mark: usize, // Messages in the container start at this index of the queue
+    key: Option<String>, // None for the element in array
+    match_candidate: Option<MatchCandidate>,
+}
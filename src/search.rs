@@ -0,0 +1,124 @@
+// Search of the characters to jump over the characters not changing the state
+//  - memchr is used for the string contents and newlines with "simd" feature (SIMD with scalar fallback in memchr)
+//  - Structural characters are classified 16 bytes at once with SSE2 (x86_64) or NEON (aarch64) with "simd" feature
+//    and searched with the table on the other targets
+
+#[cfg(feature = "simd")]
+pub use memchr_search::{find_quote_or_escape, find_newlines};
+#[cfg(not(feature = "simd"))]
+pub use scalar::{find_quote_or_escape, find_newlines};
+#[cfg(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use vector::find_structural;
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+pub use scalar::find_structural;
+
+// Characters changing the state when waiting the key: " { } [ ] ,
+//  - "[" and "]" are "{" and "}" with the bit of 0x20 unset, so they are compared after setting the bit
+#[cfg(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod vector {
+    const LANES: usize = 16;
+
+    // Position of the next structural character out of the string
+    pub fn find_structural(bytes: &[u8]) -> Option<usize> {
+        let mut chunks = bytes.chunks_exact(LANES);
+        let mut offset = 0;
+        for chunk in &mut chunks {
+            let mask = structural_mask(chunk);
+            if mask != 0 {
+                return Some(offset + first_lane(mask))
+            }
+            offset += LANES;
+        }
+        super::scalar::find_structural(chunks.remainder()).map(|pos| offset + pos)
+    }
+
+    // Bit of each lane is set for the structural character
+    #[cfg(target_arch = "x86_64")]
+    fn structural_mask(chunk: &[u8]) -> u64 {
+        use std::arch::x86_64::*;
+        // SSE2 is in the baseline of x86_64 and the chunk has 16 bytes to load
+        unsafe {
+            let chrs = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let folded = _mm_or_si128(chrs, _mm_set1_epi8(0x20));
+            let found = _mm_or_si128(
+                _mm_or_si128(_mm_cmpeq_epi8(chrs, _mm_set1_epi8(b'"' as i8)), _mm_cmpeq_epi8(chrs, _mm_set1_epi8(b',' as i8))),
+                _mm_or_si128(_mm_cmpeq_epi8(folded, _mm_set1_epi8(b'{' as i8)), _mm_cmpeq_epi8(folded, _mm_set1_epi8(b'}' as i8))),
+            );
+            _mm_movemask_epi8(found) as u64
+        }
+    }
+    #[cfg(target_arch = "x86_64")]
+    fn first_lane(mask: u64) -> usize {
+        mask.trailing_zeros() as usize
+    }
+
+    // Four bits of each lane are set for the structural character (NEON has no movemask)
+    #[cfg(target_arch = "aarch64")]
+    fn structural_mask(chunk: &[u8]) -> u64 {
+        use std::arch::aarch64::*;
+        // NEON is in the baseline of aarch64 and the chunk has 16 bytes to load
+        unsafe {
+            let chrs = vld1q_u8(chunk.as_ptr());
+            let folded = vorrq_u8(chrs, vdupq_n_u8(0x20));
+            let found = vorrq_u8(
+                vorrq_u8(vceqq_u8(chrs, vdupq_n_u8(b'"')), vceqq_u8(chrs, vdupq_n_u8(b','))),
+                vorrq_u8(vceqq_u8(folded, vdupq_n_u8(b'{')), vceqq_u8(folded, vdupq_n_u8(b'}'))),
+            );
+            vget_lane_u64(vreinterpret_u64_u8(vshrn_n_u16(vreinterpretq_u16_u8(found), 4)), 0)
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    fn first_lane(mask: u64) -> usize {
+        mask.trailing_zeros() as usize / 4
+    }
+}
+
+#[cfg(feature = "simd")]
+mod memchr_search {
+    // Position of the next '"' or '\' in the string
+    pub fn find_quote_or_escape(bytes: &[u8]) -> Option<usize> {
+        memchr::memchr2(b'"', b'\\', bytes)
+    }
+
+    // Count of newlines and position of the last newline
+    pub fn find_newlines(bytes: &[u8]) -> Option<(usize, usize)> {
+        memchr::memrchr(b'\n', bytes).map(|last| (memchr::memchr_iter(b'\n', bytes).count(), last))
+    }
+}
+
+// Always compiled to be compared with SIMD search in tests and benchmark
+pub mod scalar {
+    // Characters changing the state when waiting the key: " { } [ ] ,
+    const STRUCTURAL_TABLE: [bool; 256] = {
+        let mut table = [false; 256];
+        table[b'"' as usize] = true;
+        table[b'{' as usize] = true;
+        table[b'}' as usize] = true;
+        table[b'[' as usize] = true;
+        table[b']' as usize] = true;
+        table[b',' as usize] = true;
+        table
+    };
+
+    // Position of the next structural character out of the string
+    pub fn find_structural(bytes: &[u8]) -> Option<usize> {
+        // Check 8 bytes at once with the table before finding the position
+        let mut chunks = bytes.chunks_exact(8);
+        let mut offset = 0;
+        for chunk in &mut chunks {
+            if chunk.iter().fold(false, |found, chr| found | STRUCTURAL_TABLE[*chr as usize]) {
+                return chunk.iter().position(|chr| STRUCTURAL_TABLE[*chr as usize]).map(|pos| offset + pos)
+            }
+            offset += 8;
+        }
+        chunks.remainder().iter().position(|chr| STRUCTURAL_TABLE[*chr as usize]).map(|pos| offset + pos)
+    }
+
+    pub fn find_quote_or_escape(bytes: &[u8]) -> Option<usize> {
+        bytes.iter().position(|chr| *chr == b'"' || *chr == b'\\')
+    }
+
+    pub fn find_newlines(bytes: &[u8]) -> Option<(usize, usize)> {
+        bytes.iter().rposition(|chr| *chr == b'\n').map(|last| (bytes.iter().filter(|chr| **chr == b'\n').count(), last))
+    }
+}
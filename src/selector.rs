@@ -0,0 +1,934 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::trie::KeyTrie;
+use crate::validator::Validator;
+
+// JSONPath-style selector of the key
+//  - $.meta.debug: "debug" in "meta" of root
+//  - $.items[*].internal: "internal" in every element of "items"
+//  - $..secret: "secret" at any depth
+//  - secret: Same as $..secret
+//  - $..debug_*: Key matched with glob ("*" and "?") at any depth
+//  - ^x-.*$: Key matched with regex at any depth
+//  - $.meta.^x-.*$: Key matched with regex (Rest of the path is the regex)
+//  - $[**].id: "id" of root object or of the objects in root array (Key of record)
+//  - $['it\'s']: Quote and backslash are escaped with backslash in brackets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    prefix: StepPrefix,
+    matcher: StepMatcher,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StepPrefix {
+    Child, // "." or "[]"
+    Descendant, // ".."
+    Record, // Zero or more array indices ("[**]")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StepMatcher {
+    Key(String),
+    AnyKey,
+    Pattern(KeyPattern),
+    Index(usize),
+    AnyIndex,
+}
+
+// Glob or regex of key compiled to regex
+#[derive(Debug, Clone)]
+struct KeyPattern {
+    source: String,
+    regex: Regex,
+    is_glob: bool,
+}
+
+// Position in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl Selector {
+    // Key at any depth
+    pub fn key(key: &str) -> Self {
+        Self {
+            steps: vec![Step {
+                prefix: StepPrefix::Descendant,
+                matcher: StepMatcher::Key(key.to_string()),
+            }],
+        }
+    }
+    // Key of the root object or of the objects in root array
+    pub fn record_key(key: &str) -> Self {
+        Self {
+            steps: vec![Step {
+                prefix: StepPrefix::Record,
+                matcher: StepMatcher::Key(key.to_string()),
+            }],
+        }
+    }
+    // Key, glob (e.g. _*, *_internal) or regex starting with "^" (e.g. ^x-.*$) at any depth
+    pub fn key_pattern(pattern: &str) -> Result<Self, String> {
+        Ok(Self {
+            steps: vec![Step {
+                prefix: StepPrefix::Descendant,
+                matcher: StepMatcher::from_key_pattern(pattern)?,
+            }],
+        })
+    }
+    pub fn parse(selector: &str) -> Result<Self, String> {
+        if !selector.starts_with('$') {
+            return Self::key_pattern(selector)
+        }
+
+        // (1) Parse steps
+        let chars: Vec<char> = selector.chars().collect();
+        let mut steps = Vec::new();
+        let mut record = false; // Key of record is next
+        let mut pos = 1;
+        while pos < chars.len() {
+            let descendant = chars[pos] == '.' && chars.get(pos + 1) == Some(&'.');
+            if record && (descendant || chars[pos] != '.' && chars[pos] != '[' || chars[pos..].starts_with(&['[', '*', '*', ']'])) {
+                return Err(format!("Key is not next to '[**]' at {} in path: {}", pos, selector))
+            }
+            let prefix = if record { StepPrefix::Record } else { StepPrefix::from_descendant(descendant) };
+            match chars[pos] {
+                '.' => {
+                    pos += if descendant { 2 } else { 1 };
+                    if chars.get(pos) == Some(&'[') {
+                        if !descendant {
+                            return Err(format!("Unexpected '[' at {} in path: {}", pos, selector))
+                        }
+                        continue_with_bracket(&chars, &mut pos, &mut steps, prefix, selector)?;
+                    } else if chars.get(pos) == Some(&'^') {
+                        // Regex to the end
+                        let pattern: String = chars[pos..].iter().collect();
+                        steps.push(Step { prefix, matcher: StepMatcher::Pattern(KeyPattern::regex(&pattern)?) });
+                        pos = chars.len();
+                    } else {
+                        let name_start = pos;
+                        while pos < chars.len() && chars[pos] != '.' && chars[pos] != '[' {
+                            pos += 1;
+                        }
+                        let name: String = chars[name_start..pos].iter().collect();
+                        let matcher = match name.as_str() {
+                            "" => return Err(format!("Empty key at {} in path: {}", name_start, selector)),
+                            "*" => StepMatcher::AnyKey,
+                            _ if name.contains(['*', '?']) => StepMatcher::Pattern(KeyPattern::glob(&name)?),
+                            _ => StepMatcher::Key(name),
+                        };
+                        steps.push(Step { prefix, matcher });
+                    }
+                },
+                '[' if chars[pos..].starts_with(&['[', '*', '*', ']']) => {
+                    record = true;
+                    pos += 4;
+                    continue
+                },
+                '[' => {
+                    continue_with_bracket(&chars, &mut pos, &mut steps, prefix, selector)?;
+                },
+                _ => {
+                    return Err(format!("Unexpected '{}' at {} in path: {}", chars[pos], pos, selector))
+                },
+            }
+            if record && matches!(steps.last(), Some(Step { matcher: StepMatcher::Index(_) | StepMatcher::AnyIndex, .. })) {
+                return Err(format!("Index is next to '[**]' in path: {}", selector))
+            }
+            record = false;
+        }
+
+        // (2) Last step has to point the key
+        if record {
+            return Err(format!("Path does not end with a key: {}", selector))
+        }
+        match steps.last() {
+            Some(Step { matcher: StepMatcher::Key(_) | StepMatcher::AnyKey | StepMatcher::Pattern(_), .. }) => {
+                Ok(Self { steps })
+            },
+            _ => {
+                Err(format!("Path does not end with a key: {}", selector))
+            },
+        }
+    }
+    // Check if the member at the path is selected
+    pub fn matches(&self, path: &[PathSegment]) -> bool {
+        Self::steps_match(&self.steps, path)
+    }
+    // Check if the member at the path can be an ancestor of selected member
+    pub fn matches_ancestor(&self, path: &[PathSegment]) -> bool {
+        Self::steps_match_ancestor(&self.steps, path)
+    }
+    // Key names to compare with the keys in document
+    fn key_names(&self) -> impl Iterator<Item = &str> {
+        self.steps.iter().filter_map(|step| match &step.matcher {
+            StepMatcher::Key(key) => Some(key.as_str()),
+            _ => None,
+        })
+    }
+    fn normalized(&self, key_normalization: &KeyNormalization) -> Self {
+        let steps = self.steps.iter().map(|step| {
+            let matcher = match &step.matcher {
+                StepMatcher::Key(key) => StepMatcher::Key(key_normalization.normalize(key).into_owned()),
+                StepMatcher::Pattern(pattern) => StepMatcher::Pattern(pattern.normalized(key_normalization)),
+                matcher => matcher.clone(),
+            };
+            Step { prefix: step.prefix.clone(), matcher }
+        }).collect();
+        Self { steps }
+    }
+    // Pattern can match the key not in the key names
+    fn has_pattern(&self) -> bool {
+        self.steps.iter().any(|step| matches!(step.matcher, StepMatcher::Pattern(_)))
+    }
+    fn steps_match(steps: &[Step], path: &[PathSegment]) -> bool {
+        match steps.first() {
+            None => path.is_empty(),
+            Some(step) => {
+                step.skippable_counts(path).any(|skip| {
+                    skip < path.len()
+                    && step.matcher.matches(&path[skip])
+                    && Self::steps_match(&steps[1..], &path[skip+1..])
+                })
+            },
+        }
+    }
+    fn steps_match_ancestor(steps: &[Step], path: &[PathSegment]) -> bool {
+        match steps.first() {
+            None => false,
+            Some(step) => {
+                step.skippable_counts(path).any(|skip| {
+                    if skip == path.len() {
+                        // Path is consumed before the step
+                        true
+                    } else {
+                        step.matcher.matches(&path[skip])
+                        && Self::steps_match_ancestor(&steps[1..], &path[skip+1..])
+                    }
+                })
+            },
+        }
+    }
+}
+
+impl Step {
+    // Counts of path segments that can be skipped before the step
+    fn skippable_counts(&self, path: &[PathSegment]) -> std::ops::RangeInclusive<usize> {
+        match self.prefix {
+            StepPrefix::Child => 0..=0,
+            StepPrefix::Descendant => 0..=path.len(),
+            StepPrefix::Record => {
+                let index_count = path.iter().take_while(|segment| matches!(segment, PathSegment::Index(_))).count();
+                0..=index_count
+            },
+        }
+    }
+}
+
+impl StepPrefix {
+    fn from_descendant(descendant: bool) -> Self {
+        if descendant { Self::Descendant } else { Self::Child }
+    }
+}
+
+// Selectors with the hash sets of the selectors of single key
+#[derive(Debug, Clone, Default)]
+struct SelectorSet {
+    descendant_keys: HashSet<String>, // $..key
+    record_keys: HashSet<String>, // Key of record
+    others: Vec<Selector>,
+}
+
+impl SelectorSet {
+    fn new(selectors: Vec<Selector>) -> Self {
+        let mut selector_set = Self::default();
+        for selector in selectors {
+            match selector.steps.as_slice() {
+                [Step { prefix: StepPrefix::Descendant, matcher: StepMatcher::Key(key) }] => {
+                    selector_set.descendant_keys.insert(key.clone());
+                },
+                [Step { prefix: StepPrefix::Record, matcher: StepMatcher::Key(key) }] => {
+                    selector_set.record_keys.insert(key.clone());
+                },
+                _ => {
+                    selector_set.others.push(selector);
+                },
+            }
+        }
+        selector_set
+    }
+    fn has_pattern(&self) -> bool {
+        self.others.iter().any(|selector| selector.has_pattern())
+    }
+    fn key_names(&self) -> impl Iterator<Item = &str> {
+        self.descendant_keys.iter().chain(self.record_keys.iter()).map(|key| key.as_str())
+            .chain(self.others.iter().flat_map(|selector| selector.key_names()))
+    }
+    fn matches(&self, path: &[PathSegment]) -> bool {
+        if let Some((PathSegment::Key(key), parent_path)) = path.split_last() {
+            if self.descendant_keys.contains(key) {
+                return true
+            }
+            if self.record_keys.contains(key) && parent_path.iter().all(|segment| matches!(segment, PathSegment::Index(_))) {
+                return true
+            }
+        }
+        self.others.iter().any(|selector| selector.matches(path))
+    }
+    fn matches_ancestor(&self, path: &[PathSegment]) -> bool {
+        !self.descendant_keys.is_empty()
+        || (!self.record_keys.is_empty() && path.iter().all(|segment| matches!(segment, PathSegment::Index(_))))
+        || self.others.iter().any(|selector| selector.matches_ancestor(path))
+    }
+}
+
+// Selectors with the way to apply them
+#[derive(Debug, Clone)]
+pub struct Filter {
+    selectors: Vec<Selector>,
+    mode: FilterMode,
+    redactions: Vec<(Selector, Vec<u8>)>, // Selector and replacement of value
+    renames: HashMap<String, Vec<u8>>, // Key and quoted new key
+    value_conditions: Vec<(ConditionTarget, ValueCondition)>, // Removed when the value meets any of them
+    key_normalization: KeyNormalization,
+    index: FilterIndex,
+}
+
+// Selectors and keys with normalization to compare with the keys in document
+#[derive(Debug, Clone, Default)]
+struct FilterIndex {
+    selectors: SelectorSet,
+    redaction_selectors: Vec<Selector>,
+    renames: HashMap<String, Vec<u8>>,
+    value_conditions: Vec<(ConditionTarget, ValueCondition)>,
+    key_trie: Option<KeyTrie>, // Key names to compare (None if keys cannot be rejected early)
+}
+
+// Value of the member to remove
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueCondition {
+    Null, // null
+    Empty, // "", [] or {}
+    Equals(Selector, Vec<u8>), // Member at the selector with the JSON text (e.g. b"\"deleted\"", b"0")
+}
+
+// What is removed when the value meets the condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionTarget {
+    Member, // Member with the value
+    Element, // Object in array of which member has the value
+}
+
+// Way to normalize the keys before comparison
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyNormalization {
+    pub ignore_case: bool, // "Password" is "password" (Unicode lowercase)
+    pub unify_separators: bool, // "user-id" is "user_id"
+    pub split_camel_case: bool, // "userId" and "UserID" are "user_id" (Case is ignored)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Remove, // Remove selected keys
+    Keep, // Keep only selected keys and their ancestors (Scalar elements in array are removed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Keep,
+    Remove,
+    RemoveIfNotContainer, // Ancestor candidate is kept only when the selected member is found in its container
+    Redact(usize), // Index of redaction
+    RemoveIfValue, // Removed only when the value meets the value condition
+}
+
+impl Filter {
+    pub fn new(selectors: Vec<Selector>, mode: FilterMode) -> Self {
+        let mut filter = Self {
+            selectors,
+            mode,
+            redactions: Vec::new(),
+            renames: HashMap::new(),
+            value_conditions: Vec::new(),
+            key_normalization: KeyNormalization::default(),
+            index: FilterIndex::default(),
+        };
+        filter.update_index();
+        filter
+    }
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+    }
+    pub fn add_redaction(&mut self, selector: Selector, replacement: Vec<u8>) {
+        self.redactions.push((selector, replacement));
+        self.update_index();
+    }
+    pub fn replacement(&self, redaction_index: usize) -> &[u8] {
+        &self.redactions[redaction_index].1
+    }
+    pub fn add_rename(&mut self, key: String, new_key: &str) {
+        self.renames.insert(key, quote_json_string(new_key).into_bytes());
+        self.update_index();
+    }
+    pub fn add_value_condition(&mut self, condition_target: ConditionTarget, value_condition: ValueCondition) {
+        let value_condition = match value_condition {
+            ValueCondition::Equals(selector, value) => ValueCondition::Equals(selector, compact_empty_container(&value)),
+            value_condition => value_condition,
+        };
+        self.value_conditions.push((condition_target, value_condition));
+        self.update_index();
+    }
+    pub fn set_key_normalization(&mut self, key_normalization: KeyNormalization) {
+        self.key_normalization = key_normalization;
+        self.update_index();
+    }
+    // Key in document to compare
+    pub fn normalize_key(&self, key: String) -> String {
+        if self.key_normalization.is_enabled() {
+            self.key_normalization.normalize(&key).into_owned()
+        } else {
+            key
+        }
+    }
+    pub fn renamed_key(&self, key: &str) -> Option<&[u8]> {
+        self.index.renames.get(key).map(|quoted_new_key| quoted_new_key.as_slice())
+    }
+    // Trie of all key names in selectors, redactions and renames
+    //  - Key not in the trie is treated as empty key
+    pub fn key_trie(&self) -> Option<&KeyTrie> {
+        self.index.key_trie.as_ref()
+    }
+    fn update_index(&mut self) {
+        // (1) Normalize keys
+        let key_normalization = &self.key_normalization;
+        let mut index = FilterIndex {
+            selectors: SelectorSet::new(self.selectors.iter().map(|selector| selector.normalized(key_normalization)).collect()),
+            redaction_selectors: self.redactions.iter().map(|(selector, _)| selector.normalized(key_normalization)).collect(),
+            renames: self.renames.iter().map(|(key, quoted_new_key)| {
+                (key_normalization.normalize(key).into_owned(), quoted_new_key.clone())
+            }).collect(),
+            value_conditions: self.value_conditions.iter().map(|(condition_target, value_condition)| match value_condition {
+                ValueCondition::Equals(selector, value) => {
+                    (*condition_target, ValueCondition::Equals(selector.normalized(key_normalization), value.clone()))
+                },
+                value_condition => (*condition_target, value_condition.clone()),
+            }).collect(),
+            key_trie: None,
+        };
+
+        // (2) Make key trie
+        let mut key_names: Vec<&str> = index.selectors.key_names()
+            .chain(index.redaction_selectors.iter().flat_map(|selector| selector.key_names()))
+            .chain(index.renames.keys().map(|key| key.as_str()))
+            .chain(index.value_conditions.iter().flat_map(|(_, value_condition)| value_condition.key_names()))
+            .collect();
+        key_names.sort_unstable();
+        key_names.dedup();
+        //  - Empty key cannot be distinguished from the rejected key
+        //  - Raw bytes of key cannot be compared with the normalized keys
+        let has_pattern = index.selectors.has_pattern()
+            || index.redaction_selectors.iter().any(|selector| selector.has_pattern())
+            || index.value_conditions.iter().any(|(_, value_condition)| match value_condition {
+                ValueCondition::Equals(selector, _) => selector.has_pattern(),
+                _ => false,
+            });
+        if !has_pattern && !key_names.contains(&"") && !key_normalization.is_enabled() {
+            let key_trie = KeyTrie::new(key_names.into_iter().map(|key| key.as_bytes()));
+            index.key_trie = Some(key_trie);
+        }
+        self.index = index;
+    }
+    // Action for the member at the path
+    pub fn key_action(&self, path: &[PathSegment]) -> KeyAction {
+        // Removal precedes redaction and redaction precedes value condition
+        match self.filtering_action(path) {
+            KeyAction::Keep => {
+                match self.index.redaction_selectors.iter().position(|selector| selector.matches(path)) {
+                    Some(redaction_index) => KeyAction::Redact(redaction_index),
+                    None if self.value_conditions_at(path).next().is_some() => KeyAction::RemoveIfValue,
+                    None => KeyAction::Keep,
+                }
+            },
+            key_action => key_action,
+        }
+    }
+    // Indices of the value conditions for the member at the path
+    pub fn value_condition_indices(&self, condition_target: ConditionTarget, path: &[PathSegment]) -> Vec<usize> {
+        self.index.value_conditions.iter().enumerate().filter(|(_, (target, value_condition))| {
+            *target == condition_target && value_condition.applies_to(path)
+        }).map(|(condition_index, _)| condition_index).collect()
+    }
+    // Whether the object at the path in array can be removed by its member
+    pub fn may_remove_element(&self, path: &[PathSegment]) -> bool {
+        self.index.value_conditions.iter().any(|(condition_target, value_condition)| match value_condition {
+            ValueCondition::Equals(selector, _) => *condition_target == ConditionTarget::Element && selector.matches_ancestor(path),
+            _ => *condition_target == ConditionTarget::Element,
+        })
+    }
+    // Whether the element at the path in array is removed unless it has the selected member in keep mode
+    pub fn removes_unselected_element(&self, path: &[PathSegment]) -> bool {
+        self.mode == FilterMode::Keep && self.filtering_action(path) != KeyAction::Keep
+    }
+    // Whether the key rejected by the key trie can be removed (Keep mode or null and empty conditions)
+    pub fn may_remove_rejected_key(&self) -> bool {
+        self.mode == FilterMode::Keep || self.index.value_conditions.iter().any(|(condition_target, value_condition)| {
+            *condition_target == ConditionTarget::Member && !matches!(value_condition, ValueCondition::Equals(_, _))
+        })
+    }
+    // Whether the container at the path can have the selected member in keep mode
+    pub fn may_have_selected(&self, path: &[PathSegment]) -> bool {
+        self.mode == FilterMode::Keep && self.filtering_action(path) == KeyAction::RemoveIfNotContainer
+    }
+    // Bytes of the scalar value to read before the decision (Longer value is kept)
+    pub fn value_length_limit(&self, condition_indices: &[usize]) -> usize {
+        condition_indices.iter().map(|condition_index| match &self.index.value_conditions[*condition_index].1 {
+            ValueCondition::Null => b"null".len(),
+            ValueCondition::Empty => b"\"\"".len(),
+            ValueCondition::Equals(_, value) => value.len(),
+        }).max().unwrap_or(0)
+    }
+    // Whether the member with the scalar value is removed
+    pub fn removes_value(&self, condition_indices: &[usize], value: &[u8]) -> bool {
+        condition_indices.iter().any(|condition_index| match &self.index.value_conditions[*condition_index].1 {
+            ValueCondition::Null => value == b"null",
+            ValueCondition::Empty => value == b"\"\"",
+            ValueCondition::Equals(_, condition_value) => value == condition_value.as_slice(),
+        })
+    }
+    // Whether the member with the empty object or array is removed
+    pub fn removes_empty_container(&self, path: &[PathSegment], is_object: bool) -> bool {
+        let empty_container: &[u8] = if is_object { b"{}" } else { b"[]" };
+        self.value_conditions_at(path).any(|value_condition| match value_condition {
+            ValueCondition::Empty => true,
+            ValueCondition::Equals(_, condition_value) => condition_value == empty_container,
+            ValueCondition::Null => false,
+        })
+    }
+    // Conditions to remove the member at the path
+    fn value_conditions_at<'a>(&'a self, path: &'a [PathSegment]) -> impl Iterator<Item = &'a ValueCondition> + 'a {
+        self.index.value_conditions.iter().filter(move |(condition_target, value_condition)| {
+            *condition_target == ConditionTarget::Member && value_condition.applies_to(path)
+        }).map(|(_, value_condition)| value_condition)
+    }
+    fn filtering_action(&self, path: &[PathSegment]) -> KeyAction {
+        match self.mode {
+            FilterMode::Remove => {
+                if self.index.selectors.matches(path) {
+                    KeyAction::Remove
+                } else {
+                    KeyAction::Keep
+                }
+            },
+            FilterMode::Keep => {
+                // Members in the kept member are all kept
+                let selected = (1..=path.len()).any(|length| {
+                    self.index.selectors.matches(&path[..length])
+                });
+                if selected {
+                    KeyAction::Keep
+                } else if self.index.selectors.matches_ancestor(path) {
+                    KeyAction::RemoveIfNotContainer
+                } else {
+                    KeyAction::Remove
+                }
+            },
+        }
+    }
+}
+
+impl ValueCondition {
+    fn applies_to(&self, path: &[PathSegment]) -> bool {
+        match self {
+            Self::Equals(selector, _) => selector.matches(path),
+            _ => true,
+        }
+    }
+    fn key_names(&self) -> Vec<&str> {
+        match self {
+            Self::Equals(selector, _) => selector.key_names().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Value is compared as JSON text, so only the whitespace around it and in the empty container is ignored
+fn compact_empty_container(value: &[u8]) -> Vec<u8> {
+    let value = value.trim_ascii();
+    match (value.first(), value.last()) {
+        (Some(opener @ (b'{' | b'[')), Some(closer)) if value.len() >= 2 && value[1..value.len()-1].trim_ascii().is_empty() => {
+            vec![*opener, *closer]
+        },
+        _ => value.to_vec(),
+    }
+}
+
+// Parse "[n]", "[*]", "['key']" or "[\"key\"]" starting at "["
+fn continue_with_bracket(
+    chars: &[char],
+    pos: &mut usize,
+    steps: &mut Vec<Step>,
+    prefix: StepPrefix,
+    selector: &str,
+) -> Result<(), String> {
+    let bracket_start = *pos;
+    *pos += 1;
+    let matcher = match chars.get(*pos) {
+        Some(quote @ ('\'' | '"')) => {
+            // Character after backslash is a part of the key
+            let quote_start = *pos;
+            let mut name = String::new();
+            *pos += 1;
+            loop {
+                match chars.get(*pos) {
+                    Some(chr) if chr == quote => break,
+                    Some('\\') if *pos + 1 < chars.len() => {
+                        name.push(chars[*pos + 1]);
+                        *pos += 2;
+                    },
+                    Some(chr) => {
+                        name.push(*chr);
+                        *pos += 1;
+                    },
+                    None => return Err(format!("Unclosed quote at {} in path: {}", quote_start, selector)),
+                }
+            }
+            *pos += 1;
+            StepMatcher::Key(name)
+        },
+        Some('*') => {
+            *pos += 1;
+            StepMatcher::AnyIndex
+        },
+        _ => {
+            let index_start = *pos;
+            while *pos < chars.len() && chars[*pos].is_ascii_digit() {
+                *pos += 1;
+            }
+            let index: String = chars[index_start..*pos].iter().collect();
+            let index = index.parse()
+                .map_err(|_| format!("Invalid index at {} in path: {}", index_start, selector))?;
+            StepMatcher::Index(index)
+        },
+    };
+    if chars.get(*pos) != Some(&']') {
+        return Err(format!("Unclosed bracket at {} in path: {}", bracket_start, selector))
+    }
+    *pos += 1;
+    steps.push(Step { prefix, matcher });
+    Ok(())
+}
+
+impl StepMatcher {
+    fn from_key_pattern(pattern: &str) -> Result<Self, String> {
+        if pattern == "*" {
+            Ok(Self::AnyKey)
+        } else if pattern.starts_with('^') {
+            Ok(Self::Pattern(KeyPattern::regex(pattern)?))
+        } else if pattern.contains(['*', '?']) {
+            Ok(Self::Pattern(KeyPattern::glob(pattern)?))
+        } else {
+            Ok(Self::Key(pattern.to_string()))
+        }
+    }
+    fn matches(&self, segment: &PathSegment) -> bool {
+        match (self, segment) {
+            (Self::Key(key), PathSegment::Key(segment_key)) => key == segment_key,
+            (Self::AnyKey, PathSegment::Key(_)) => true,
+            (Self::Pattern(pattern), PathSegment::Key(segment_key)) => pattern.regex.is_match(segment_key),
+            (Self::Index(index), PathSegment::Index(segment_index)) => index == segment_index,
+            (Self::AnyIndex, PathSegment::Index(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl KeyPattern {
+    fn regex(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|err| format!("Invalid regex {}: {}", pattern, err))?;
+        Ok(Self { source: pattern.to_string(), regex, is_glob: false })
+    }
+    // Regex is matched with the normalized key ignoring case
+    //  - Literal "-" in regex is "_" with the unified separators
+    fn normalized(&self, key_normalization: &KeyNormalization) -> Self {
+        if !key_normalization.is_enabled() {
+            return self.clone()
+        }
+        let normalized = if self.is_glob {
+            Self::glob(&key_normalization.normalize(&self.source))
+        } else {
+            let source = if key_normalization.unify_separators {
+                unify_separators_in_regex(&self.source)
+            } else {
+                self.source.clone()
+            };
+            if key_normalization.ignore_case || key_normalization.split_camel_case {
+                Self::regex(&format!("(?i){}", source))
+            } else {
+                Self::regex(&source)
+            }
+        };
+        // Normalized pattern is valid as the source is valid
+        normalized.map(|pattern| Self { source: self.source.clone(), ..pattern }).unwrap_or_else(|_| self.clone())
+    }
+    // "*" is any characters and "?" is a character
+    fn glob(pattern: &str) -> Result<Self, String> {
+        let mut regex_pattern = String::from("(?s)^");
+        for chr in pattern.chars() {
+            match chr {
+                '*' => regex_pattern.push_str(".*"),
+                '?' => regex_pattern.push('.'),
+                _ => regex_pattern.push_str(&regex::escape(&chr.to_string())),
+            }
+        }
+        regex_pattern.push('$');
+        let regex = Regex::new(&regex_pattern).map_err(|err| format!("Invalid glob {}: {}", pattern, err))?;
+        Ok(Self { source: pattern.to_string(), regex, is_glob: true })
+    }
+}
+
+// Replace literal "-" in regex with "_"
+//  - Range in class ("[a-z]") and negated flag ("(?-i)") are kept
+fn unify_separators_in_regex(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut unified = String::with_capacity(pattern.len());
+    let mut class_depth = 0;
+    let mut pos = 0;
+    while pos < chars.len() {
+        let chr = chars[pos];
+        match chr {
+            '\\' => {
+                match chars.get(pos + 1) {
+                    Some('-') => unified.push('_'),
+                    Some(escaped) => {
+                        unified.push(chr);
+                        unified.push(*escaped);
+                    },
+                    None => unified.push(chr),
+                }
+                pos += 2;
+                continue
+            },
+            '(' if class_depth == 0 && chars.get(pos + 1) == Some(&'?') => {
+                // Flags or group name
+                while pos < chars.len() && !matches!(chars[pos], ')' | ':' | '>') {
+                    unified.push(chars[pos]);
+                    pos += 1;
+                }
+                continue
+            },
+            '[' => {
+                class_depth += 1;
+                unified.push(chr);
+                pos += 1;
+                // "]" and "-" at the start of class are literal
+                if chars.get(pos) == Some(&'^') {
+                    unified.push('^');
+                    pos += 1;
+                }
+                match chars.get(pos) {
+                    Some(']') => unified.push(']'),
+                    Some('-') => unified.push('_'),
+                    _ => continue,
+                }
+            },
+            ']' if class_depth > 0 => {
+                class_depth -= 1;
+                unified.push(chr);
+            },
+            '-' if class_depth == 0 || chars.get(pos + 1) == Some(&']') => unified.push('_'),
+            _ => unified.push(chr),
+        }
+        pos += 1;
+    }
+    unified
+}
+
+impl KeyNormalization {
+    pub fn is_enabled(&self) -> bool {
+        self.ignore_case || self.unify_separators || self.split_camel_case
+    }
+    pub fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if !self.is_enabled() {
+            return Cow::Borrowed(key)
+        }
+        let chars: Vec<char> = key.chars().collect();
+        let mut normalized = String::with_capacity(key.len());
+        for (idx, chr) in chars.iter().enumerate() {
+            let chr = if self.unify_separators && *chr == '-' { '_' } else { *chr };
+            if self.split_camel_case && chr.is_uppercase() && idx > 0 {
+                // Word starts at "aB", "1B" and "AbC" of "ABc"
+                let previous = chars[idx - 1];
+                let next_is_lowercase = chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+                if previous.is_lowercase() || previous.is_numeric() || (previous.is_uppercase() && next_is_lowercase) {
+                    normalized.push('_');
+                }
+            }
+            if self.ignore_case || self.split_camel_case {
+                normalized.extend(chr.to_lowercase());
+            } else {
+                normalized.push(chr);
+            }
+        }
+        Cow::Owned(normalized)
+    }
+}
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+impl Eq for KeyPattern {}
+
+// Split the list of keys or JSONPaths at the delimiter out of the brackets (e.g. "$['a,b'],c" to "$['a,b']" and "c")
+pub fn split_outside_brackets(text: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(idx) = find_outside_brackets(rest, delimiter) {
+        parts.push(&rest[..idx]);
+        rest = &rest[idx + delimiter.len_utf8()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+// Split at the first delimiter out of the brackets (e.g. "$['a=b']=1" to "$['a=b']" and "1")
+pub fn split_once_outside_brackets(text: &str, delimiter: char) -> Option<(&str, &str)> {
+    find_outside_brackets(text, delimiter).map(|idx| (&text[..idx], &text[idx + delimiter.len_utf8()..]))
+}
+
+// Position of the delimiter out of the brackets and the quoted keys in them
+fn find_outside_brackets(text: &str, delimiter: char) -> Option<usize> {
+    let mut in_bracket = false;
+    let mut quote = None;
+    let mut escape_next = false;
+    for (idx, chr) in text.char_indices() {
+        match quote {
+            Some(_) if escape_next => escape_next = false,
+            Some(_) if chr == '\\' => escape_next = true,
+            Some(quote_chr) if chr == quote_chr => quote = None,
+            Some(_) => {},
+            None if in_bracket && (chr == '\'' || chr == '"') => quote = Some(chr),
+            None if chr == '[' => in_bracket = true,
+            None if chr == ']' => in_bracket = false,
+            None if chr == delimiter && !in_bracket => return Some(idx),
+            None => {},
+        }
+    }
+    None
+}
+
+// Text that is not JSON value is quoted as JSON string
+pub fn replacement_to_json(replacement: &str) -> Vec<u8> {
+    let mut validator = Validator::default();
+    let is_json_value = replacement.bytes().all(|chr| validator.validate(chr).is_ok())
+        && validator.finish().is_ok();
+    if is_json_value {
+        replacement.as_bytes().to_vec()
+    } else {
+        quote_json_string(replacement).into_bytes()
+    }
+}
+
+// JSON string of the text (Control characters are escaped)
+pub fn quote_json_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for chr in string.chars() {
+        match chr {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            chr if (chr as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", chr as u32)),
+            chr => quoted.push(chr),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Glob with "." or "[" is parsed back only without "$" (e.g. *.bak)
+        if let [Step { prefix: StepPrefix::Descendant, matcher: StepMatcher::Pattern(pattern) }] = self.steps.as_slice() {
+            if pattern.is_glob && pattern.source.contains(['.', '[']) {
+                return write!(f, "{}", pattern.source)
+            }
+        }
+        write!(f, "$")?;
+        for step in &self.steps {
+            let descendant = step.prefix == StepPrefix::Descendant;
+            match step.prefix {
+                StepPrefix::Child => {},
+                StepPrefix::Descendant => write!(f, "..")?,
+                StepPrefix::Record => write!(f, "[**]")?,
+            }
+            match &step.matcher {
+                StepMatcher::Key(key) => write_key(f, key, descendant)?,
+                StepMatcher::AnyKey => {
+                    if !descendant {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "*")?;
+                },
+                StepMatcher::Pattern(pattern) => {
+                    if !descendant {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", pattern.source)?;
+                },
+                StepMatcher::Index(index) => write!(f, "[{}]", index)?,
+                StepMatcher::AnyIndex => write!(f, "[*]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write_key(f, key, false),
+            Self::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+// Key in dot form or in bracket form if it cannot be parsed back from dot form
+fn write_key(f: &mut fmt::Formatter<'_>, key: &str, descendant: bool) -> fmt::Result {
+    if !key.is_empty() && !key.starts_with('^') && !key.contains(['.', '[', ']', '\'', '"', '*', '?']) {
+        let dot = if descendant { "" } else { "." };
+        return write!(f, "{}{}", dot, key)
+    }
+    let quote = if key.contains('\'') && !key.contains('"') { '"' } else { '\'' };
+    write!(f, "[{}", quote)?;
+    for chr in key.chars() {
+        if chr == quote || chr == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", chr)?;
+    }
+    write!(f, "{}]", quote)
+}
@@ -0,0 +1,33 @@
+// Trie of the key names in selectors to reject the other keys while reading them
+#[derive(Debug, Clone)]
+pub struct KeyTrie {
+    nodes: Vec<Vec<(u8, usize)>>, // Children of each node sorted by byte
+}
+
+impl KeyTrie {
+    pub const ROOT: usize = 0;
+
+    pub fn new<'a, I: Iterator<Item = &'a [u8]>>(keys: I) -> Self {
+        let mut trie = Self { nodes: vec![Vec::new()] };
+        for key in keys {
+            let mut node = Self::ROOT;
+            for chr in key {
+                node = match trie.nodes[node].binary_search_by_key(chr, |(child_chr, _)| *child_chr) {
+                    Ok(child_index) => trie.nodes[node][child_index].1,
+                    Err(child_index) => {
+                        let new_node = trie.nodes.len();
+                        trie.nodes.push(Vec::new());
+                        trie.nodes[node].insert(child_index, (*chr, new_node));
+                        new_node
+                    },
+                };
+            }
+        }
+        trie
+    }
+    // Node after the character (None if no key has the prefix)
+    pub fn next(&self, node: usize, chr: u8) -> Option<usize> {
+        let children = &self.nodes[node];
+        children.binary_search_by_key(&chr, |(child_chr, _)| *child_chr).ok().map(|child_index| children[child_index].1)
+    }
+}
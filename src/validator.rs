@@ -0,0 +1,300 @@
+// Streaming validator of JSON grammar (RFC 8259)
+
+#[derive(Debug)]
+pub struct Validator {
+    state: ValidatorState,
+    containers: Vec<Container>,
+    utf8_continuation: Utf8Continuation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValidatorState {
+    WaitingValue, // At the start, after ':' or after ',' in array
+    WaitingValueOrArrayEnd, // After '['
+    WaitingKey, // After ',' in object
+    WaitingKeyOrObjectEnd, // After '{'
+    WaitingColon,
+    WaitingCommaOrEnd, // After value in container
+    InString { is_key: bool },
+    InEscape { is_key: bool },
+    InUnicodeEscape { is_key: bool, remained_hex: u8 },
+    InNumber(NumberState),
+    InLiteral { literal: &'static [u8], matched: usize },
+    Finished, // After top-level value
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberState {
+    Minus, // -
+    Zero, // 0
+    Integer, // [1-9][0-9]*
+    FractionStart, // .
+    Fraction, // .[0-9]+
+    ExponentStart, // e or E
+    ExponentSign, // + or -
+    Exponent, // [0-9]+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+// Remained continuation bytes of multi-byte UTF-8 character
+#[derive(Debug, Clone, Copy, Default)]
+struct Utf8Continuation {
+    remained: u8,
+    lower: u8, // Range of the next byte
+    upper: u8,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self {
+            state: ValidatorState::WaitingValue,
+            containers: Vec::new(),
+            utf8_continuation: Utf8Continuation::default(),
+        }
+    }
+}
+
+impl Validator {
+    pub fn validate(&mut self, chr: u8) -> Result<(), &'static str> {
+        // Number is closed by the next character
+        if let ValidatorState::InNumber(number_state) = self.state {
+            match next_number_state(number_state, chr) {
+                Some(next_state) => {
+                    self.state = ValidatorState::InNumber(next_state);
+                    return Ok(())
+                },
+                None if number_state == NumberState::Zero && chr.is_ascii_digit() => {
+                    return Err("Leading zero in number")
+                },
+                None => {
+                    if !matches!(number_state, NumberState::Zero | NumberState::Integer | NumberState::Fraction | NumberState::Exponent) {
+                        return Err("Number is not completed")
+                    }
+                    self.close_value();
+                },
+            }
+        }
+
+        match self.state {
+            ValidatorState::WaitingValue | ValidatorState::WaitingValueOrArrayEnd => {
+                if is_whitespace(chr) {
+                    return Ok(())
+                }
+                if chr == b']' && self.state == ValidatorState::WaitingValueOrArrayEnd {
+                    return self.close_container(Container::Array)
+                }
+                self.open_value(chr)
+            },
+            ValidatorState::WaitingKey | ValidatorState::WaitingKeyOrObjectEnd => {
+                match chr {
+                    _ if is_whitespace(chr) => Ok(()),
+                    b'"' => {
+                        self.state = ValidatorState::InString { is_key: true };
+                        Ok(())
+                    },
+                    b'}' if self.state == ValidatorState::WaitingKeyOrObjectEnd => {
+                        self.close_container(Container::Object)
+                    },
+                    b'}' => Err("Trailing comma in object"),
+                    _ => Err("Expected key in object"),
+                }
+            },
+            ValidatorState::WaitingColon => {
+                match chr {
+                    _ if is_whitespace(chr) => Ok(()),
+                    b':' => {
+                        self.state = ValidatorState::WaitingValue;
+                        Ok(())
+                    },
+                    _ => Err("Expected ':' after key"),
+                }
+            },
+            ValidatorState::WaitingCommaOrEnd => {
+                match (chr, self.containers.last()) {
+                    (_, _) if is_whitespace(chr) => Ok(()),
+                    (b',', Some(Container::Object)) => {
+                        self.state = ValidatorState::WaitingKey;
+                        Ok(())
+                    },
+                    (b',', Some(Container::Array)) => {
+                        self.state = ValidatorState::WaitingValue;
+                        Ok(())
+                    },
+                    (b'}', _) => self.close_container(Container::Object),
+                    (b']', _) => self.close_container(Container::Array),
+                    (_, Some(Container::Object)) => Err("Expected ',' or '}' after value in object"),
+                    _ => Err("Expected ',' or ']' after value in array"),
+                }
+            },
+            ValidatorState::InString { is_key } => {
+                if self.utf8_continuation.remained != 0 {
+                    return self.utf8_continuation.validate(chr)
+                }
+                match chr {
+                    b'"' => {
+                        if is_key {
+                            self.state = ValidatorState::WaitingColon;
+                        } else {
+                            self.close_value();
+                        }
+                        Ok(())
+                    },
+                    b'\\' => {
+                        self.state = ValidatorState::InEscape { is_key };
+                        Ok(())
+                    },
+                    0x00..=0x1F => Err("Control character in string"),
+                    0x20..=0x7F => Ok(()),
+                    _ => self.utf8_continuation.start(chr),
+                }
+            },
+            ValidatorState::InEscape { is_key } => {
+                match chr {
+                    b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => {
+                        self.state = ValidatorState::InString { is_key };
+                        Ok(())
+                    },
+                    b'u' => {
+                        self.state = ValidatorState::InUnicodeEscape { is_key, remained_hex: 4 };
+                        Ok(())
+                    },
+                    _ => Err("Invalid escape in string"),
+                }
+            },
+            ValidatorState::InUnicodeEscape { is_key, remained_hex } => {
+                if !chr.is_ascii_hexdigit() {
+                    return Err("Invalid unicode escape in string")
+                }
+                self.state = if remained_hex == 1 {
+                    ValidatorState::InString { is_key }
+                } else {
+                    ValidatorState::InUnicodeEscape { is_key, remained_hex: remained_hex - 1 }
+                };
+                Ok(())
+            },
+            ValidatorState::InNumber(_) => {
+                // Number is already handled
+                Ok(())
+            },
+            ValidatorState::InLiteral { literal, matched } => {
+                if literal[matched] != chr {
+                    return Err("Invalid literal")
+                }
+                if matched + 1 == literal.len() {
+                    self.close_value();
+                } else {
+                    self.state = ValidatorState::InLiteral { literal, matched: matched + 1 };
+                }
+                Ok(())
+            },
+            ValidatorState::Finished => {
+                if is_whitespace(chr) {
+                    Ok(())
+                } else {
+                    Err("Unexpected character after the top-level value")
+                }
+            },
+        }
+    }
+    pub fn finish(&mut self) -> Result<(), &'static str> {
+        if let ValidatorState::InNumber(number_state) = self.state {
+            if !matches!(number_state, NumberState::Zero | NumberState::Integer | NumberState::Fraction | NumberState::Exponent) {
+                return Err("Number is not completed")
+            }
+            self.close_value();
+        }
+        match self.state {
+            ValidatorState::Finished => Ok(()),
+            ValidatorState::WaitingValue if self.containers.is_empty() => Err("Input has no value"),
+            _ => Err("Unexpected end of input"),
+        }
+    }
+    fn open_value(&mut self, chr: u8) -> Result<(), &'static str> {
+        self.state = match chr {
+            b'{' => {
+                self.containers.push(Container::Object);
+                ValidatorState::WaitingKeyOrObjectEnd
+            },
+            b'[' => {
+                self.containers.push(Container::Array);
+                ValidatorState::WaitingValueOrArrayEnd
+            },
+            b'"' => ValidatorState::InString { is_key: false },
+            b'-' => ValidatorState::InNumber(NumberState::Minus),
+            b'0' => ValidatorState::InNumber(NumberState::Zero),
+            b'1'..=b'9' => ValidatorState::InNumber(NumberState::Integer),
+            b't' => ValidatorState::InLiteral { literal: b"true", matched: 1 },
+            b'f' => ValidatorState::InLiteral { literal: b"false", matched: 1 },
+            b'n' => ValidatorState::InLiteral { literal: b"null", matched: 1 },
+            b']' => return Err("Trailing comma in array"),
+            _ => return Err("Expected value"),
+        };
+        Ok(())
+    }
+    fn close_container(&mut self, container: Container) -> Result<(), &'static str> {
+        match self.containers.pop() {
+            Some(opened) if opened == container => {
+                self.close_value();
+                Ok(())
+            },
+            Some(Container::Object) => Err("Object is closed with ']'"),
+            _ => Err("Array is closed with '}'"),
+        }
+    }
+    fn close_value(&mut self) {
+        self.state = if self.containers.is_empty() {
+            ValidatorState::Finished
+        } else {
+            ValidatorState::WaitingCommaOrEnd
+        };
+    }
+}
+
+fn next_number_state(number_state: NumberState, chr: u8) -> Option<NumberState> {
+    match (number_state, chr) {
+        (NumberState::Minus, b'0') => Some(NumberState::Zero),
+        (NumberState::Minus, b'1'..=b'9') => Some(NumberState::Integer),
+        (NumberState::Integer, b'0'..=b'9') => Some(NumberState::Integer),
+        (NumberState::Zero | NumberState::Integer, b'.') => Some(NumberState::FractionStart),
+        (NumberState::FractionStart | NumberState::Fraction, b'0'..=b'9') => Some(NumberState::Fraction),
+        (NumberState::Zero | NumberState::Integer | NumberState::Fraction, b'e' | b'E') => Some(NumberState::ExponentStart),
+        (NumberState::ExponentStart, b'+' | b'-') => Some(NumberState::ExponentSign),
+        (NumberState::ExponentStart | NumberState::ExponentSign | NumberState::Exponent, b'0'..=b'9') => Some(NumberState::Exponent),
+        _ => None,
+    }
+}
+
+fn is_whitespace(chr: u8) -> bool {
+    matches!(chr, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+impl Utf8Continuation {
+    fn start(&mut self, chr: u8) -> Result<(), &'static str> {
+        let (remained, lower, upper) = match chr {
+            0xC2..=0xDF => (1, 0x80, 0xBF),
+            0xE0 => (2, 0xA0, 0xBF), // Overlong
+            0xE1..=0xEC | 0xEE..=0xEF => (2, 0x80, 0xBF),
+            0xED => (2, 0x80, 0x9F), // Surrogate
+            0xF0 => (3, 0x90, 0xBF), // Overlong
+            0xF1..=0xF3 => (3, 0x80, 0xBF),
+            0xF4 => (3, 0x80, 0x8F), // Over U+10FFFF
+            _ => return Err("Invalid UTF-8 in string"),
+        };
+        *self = Self { remained, lower, upper };
+        Ok(())
+    }
+    fn validate(&mut self, chr: u8) -> Result<(), &'static str> {
+        if chr < self.lower || chr > self.upper {
+            return Err("Invalid UTF-8 in string")
+        }
+        self.remained -= 1;
+        self.lower = 0x80;
+        self.upper = 0xBF;
+        Ok(())
+    }
+}